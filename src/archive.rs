@@ -0,0 +1,290 @@
+use std::env;
+use std::fs::{remove_file, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Result, anyhow};
+use bzip2::read::BzDecoder;
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use log::info;
+use tar::{Archive, EntryType};
+use zip::{CompressionMethod, ZipArchive};
+
+/// Bytes needed to recognise every supported format, up to the tar `ustar` magic.
+const SNIFF_LEN: usize = 262;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Plain,
+    Gzip,
+    Bzip2,
+    Zstd,
+    Tar,
+    Zip,
+}
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Format::Plain => "json",
+            Format::Gzip => "gzip",
+            Format::Bzip2 => "bzip2",
+            Format::Zstd => "zstd",
+            Format::Tar => "tar",
+            Format::Zip => "zip",
+        }
+    }
+}
+
+/// Recognises a format from the first bytes of a file.
+pub fn sniff(magic: &[u8]) -> Format {
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        Format::Gzip
+    } else if magic.starts_with(b"BZh") {
+        Format::Bzip2
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Format::Zstd
+    } else if magic.starts_with(b"PK\x03\x04") {
+        Format::Zip
+    } else if magic.len() >= 262 && &magic[257..262] == b"ustar" {
+        Format::Tar
+    } else {
+        Format::Plain
+    }
+}
+
+/// Format implied by an http Content-Type, used when the content itself is not recognised.
+fn content_type_format(content_type: &str) -> Option<Format> {
+    match content_type.split(';').next().unwrap_or_default().trim() {
+        "application/gzip" | "application/x-gzip" => Some(Format::Gzip),
+        "application/x-bzip2" => Some(Format::Bzip2),
+        "application/zstd" => Some(Format::Zstd),
+        "application/x-tar" => Some(Format::Tar),
+        "application/zip" | "application/x-zip-compressed" => Some(Format::Zip),
+        _ => None,
+    }
+}
+
+fn read_prefix(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let mut prefix = vec![];
+    reader.take(SNIFF_LEN as u64).read_to_end(&mut prefix)?;
+    Ok(prefix)
+}
+
+fn spool_path() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    env::temp_dir().join(format!("starsign-{}-{}.archive", process::id(), COUNTER.fetch_add(1, Ordering::SeqCst)))
+}
+
+/// Copies an archive to a temporary file so its members can be located, returning the open file.
+fn spool(reader: impl Read) -> Result<File> {
+    let path = spool_path();
+    let result = File::options().read(true).write(true).create_new(true).open(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| {
+            io::copy(&mut { reader }, &mut file)?;
+            file.seek(SeekFrom::Start(0))?;
+            Ok(file)
+        });
+    let _ = remove_file(&path);
+    result
+}
+
+/// Picks `genesis.json`, or the only JSON member, from an archive listing.
+fn select_member(names: &[String]) -> Result<usize> {
+    let base = |n: &str| Path::new(n).file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+    if let Some(i) = names.iter().position(|n| base(n) == "genesis.json") {
+        return Ok(i);
+    }
+    let json: Vec<usize> = (0..names.len()).filter(|i| base(&names[*i]).ends_with(".json")).collect();
+    match json.as_slice() {
+        [i] => Ok(*i),
+        [] => Err(anyhow!("archive has no json member")),
+        _ => Err(anyhow!(
+            "archive has several json members and none is genesis.json: {}",
+            json.iter().map(|i| names[*i].as_str()).collect::<Vec<&str>>().join(", "),
+        )),
+    }
+}
+
+fn open_tar(reader: impl Read) -> Result<Box<dyn Read>> {
+    let mut file = spool(reader)?;
+    let mut members = vec![];
+    for entry in Archive::new(&mut file).entries_with_seek()? {
+        let entry = entry?;
+        if entry.header().entry_type() == EntryType::Regular {
+            members.push((entry.path()?.to_string_lossy().to_string(), entry.raw_file_position(), entry.size()));
+        }
+    }
+    let names: Vec<String> = members.iter().map(|m| m.0.clone()).collect();
+    let (name, position, size) = &members[select_member(&names)?];
+    info!("extracting {} from tar archive", name);
+    file.seek(SeekFrom::Start(*position))?;
+    Ok(Box::new(file.take(*size)))
+}
+
+fn open_zip(reader: impl Read) -> Result<Box<dyn Read>> {
+    let mut zip = ZipArchive::new(spool(reader)?)?;
+    let mut members = vec![];
+    for i in 0..zip.len() {
+        let member = zip.by_index_raw(i)?;
+        if member.is_file() {
+            members.push((member.name().to_string(), member.data_start(), member.compressed_size(), member.compression()));
+        }
+    }
+    let names: Vec<String> = members.iter().map(|m| m.0.clone()).collect();
+    let (name, start, size, compression) = &members[select_member(&names)?];
+    info!("extracting {} from zip archive", name);
+    let mut file = zip.into_inner();
+    file.seek(SeekFrom::Start(*start))?;
+    let data = file.take(*size);
+    match compression {
+        CompressionMethod::Stored => Ok(Box::new(data)),
+        CompressionMethod::Deflated => Ok(Box::new(DeflateDecoder::new(data))),
+        method => Err(anyhow!("unsupported zip compression {} for {}", method, name)),
+    }
+}
+
+/// Unwraps compressed and archived genesis files until plain JSON remains.
+pub fn open_genesis(reader: impl Read + 'static, content_type: Option<&str>) -> Result<Box<dyn Read>> {
+    let mut reader: Box<dyn Read> = Box::new(reader);
+    let mut content_type = content_type.map(str::to_string);
+    loop {
+        let prefix = read_prefix(&mut reader)?;
+        let mut format = sniff(&prefix);
+        if format == Format::Plain {
+            if let Some(f) = content_type.as_deref().and_then(content_type_format) {
+                format = f;
+            }
+        }
+        content_type = None;
+        let chained = Cursor::new(prefix).chain(reader);
+        if format != Format::Plain {
+            info!("unpacking {} genesis", format.name());
+        }
+        reader = match format {
+            Format::Plain => return Ok(Box::new(chained)),
+            Format::Gzip => Box::new(MultiGzDecoder::new(chained)),
+            Format::Bzip2 => Box::new(BzDecoder::new(chained)),
+            Format::Zstd => Box::new(zstd::Decoder::new(chained)?),
+            Format::Tar => open_tar(chained)?,
+            Format::Zip => open_zip(chained)?,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use bzip2::write::BzEncoder;
+    use flate2::write::GzEncoder;
+    use tar::{Builder, Header};
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    const GENESIS: &[u8] = br#"{"chain_id": "kaiyo-1"}"#;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn bzip2(data: &[u8]) -> Vec<u8> {
+        let mut encoder = BzEncoder::new(vec![], bzip2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn tar(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(vec![]);
+        for (name, data) in members {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn zip(members: &[(&str, &[u8])], method: CompressionMethod) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, data) in members {
+            writer.start_file(*name, FileOptions::default().compression_method(method)).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn unpack(data: Vec<u8>, content_type: Option<&str>) -> Result<Vec<u8>> {
+        let mut genesis = vec![];
+        open_genesis(Cursor::new(data), content_type)?.read_to_end(&mut genesis)?;
+        Ok(genesis)
+    }
+
+    #[test]
+    fn unpacks_every_format() {
+        let archives = [
+            GENESIS.to_vec(),
+            gzip(GENESIS),
+            bzip2(GENESIS),
+            zstd::encode_all(GENESIS, 0).unwrap(),
+            tar(&[("genesis.json", GENESIS)]),
+            zip(&[("genesis.json", GENESIS)], CompressionMethod::Stored),
+            zip(&[("genesis.json", GENESIS)], CompressionMethod::Deflated),
+            gzip(&tar(&[("config/genesis.json", GENESIS)])),
+            zstd::encode_all(&zip(&[("genesis.json", GENESIS)], CompressionMethod::Deflated)[..], 0).unwrap(),
+        ];
+        for (i, archive) in archives.into_iter().enumerate() {
+            assert_eq!(unpack(archive, None).unwrap(), GENESIS, "archive {}", i);
+        }
+    }
+
+    #[test]
+    fn sniffs_formats_from_magic_bytes() {
+        assert!(sniff(&gzip(GENESIS)) == Format::Gzip);
+        assert!(sniff(&bzip2(GENESIS)) == Format::Bzip2);
+        assert!(sniff(&zstd::encode_all(GENESIS, 0).unwrap()) == Format::Zstd);
+        assert!(sniff(&tar(&[("genesis.json", GENESIS)])[..SNIFF_LEN]) == Format::Tar);
+        assert!(sniff(&zip(&[("genesis.json", GENESIS)], CompressionMethod::Stored)) == Format::Zip);
+        assert!(sniff(GENESIS) == Format::Plain);
+        assert!(sniff(b"") == Format::Plain);
+    }
+
+    #[test]
+    fn falls_back_to_the_content_type_only_for_the_outer_layer() {
+        // a tar without the ustar magic is only recognised by its content type
+        let mut v7 = tar(&[("genesis.json", GENESIS)]);
+        v7[257..265].fill(0);
+        let mut header = Header::from_byte_slice(&v7[..512]).clone();
+        header.set_cksum();
+        v7[..512].copy_from_slice(header.as_bytes());
+        assert_eq!(unpack(v7.clone(), Some("application/x-tar; charset=binary")).unwrap(), GENESIS);
+        assert_eq!(unpack(v7.clone(), None).unwrap(), v7);
+        // the content type describes the download, not what is inside it
+        assert_eq!(unpack(gzip(GENESIS), Some("application/x-tar")).unwrap(), GENESIS);
+        assert_eq!(unpack(GENESIS.to_vec(), Some("application/json")).unwrap(), GENESIS);
+    }
+
+    #[test]
+    fn selects_the_genesis_member() {
+        let names = |n: &[&str]| n.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        assert_eq!(select_member(&names(&["README.md", "a.json", "config/genesis.json"])).unwrap(), 2);
+        assert_eq!(select_member(&names(&["README.md", "kaiyo-1.json"])).unwrap(), 1);
+        assert_eq!(select_member(&names(&["README.md"])).unwrap_err().to_string(), "archive has no json member");
+        assert_eq!(
+            select_member(&names(&["a.json", "b.json"])).unwrap_err().to_string(),
+            "archive has several json members and none is genesis.json: a.json, b.json",
+        );
+        let archive = tar(&[("README.md", b"readme"), ("pre.json", b"{}"), ("post.json", b"{}")]);
+        assert!(unpack(archive, None).is_err());
+        let archive = zip(&[("README.md", b"readme"), ("data/genesis.json", GENESIS)], CompressionMethod::Deflated);
+        assert_eq!(unpack(archive, None).unwrap(), GENESIS);
+    }
+}
@@ -0,0 +1,262 @@
+use std::fs::{copy, hard_link, read_dir, remove_file, rename, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow};
+
+/// Milliseconds since the unix epoch.
+pub fn timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    /// `<name>.<stamp>.bak`, the file as it was before a run wrote it
+    Copy,
+    /// `<name>.<stamp>.new`, an empty marker for a file the run created
+    Created,
+    /// `<name>.<stamp>.undone`, the file as it was before a rollback replaced or removed it
+    Undone,
+}
+
+impl Kind {
+    const ALL: [Kind; 3] = [Kind::Copy, Kind::Created, Kind::Undone];
+
+    fn suffix(self) -> &'static str {
+        match self {
+            Kind::Copy => "bak",
+            Kind::Created => "new",
+            Kind::Undone => "undone",
+        }
+    }
+}
+
+struct Backup {
+    name: String,
+    stamp: u64,
+    kind: Kind,
+    path: PathBuf,
+}
+
+fn dir_of(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or(anyhow!("invalid file path: {}", path.to_string_lossy()))
+}
+
+/// Splits `<name>.<stamp>.<suffix>` into its original file name, stamp and kind.
+fn parse_backup_name(name: &str) -> Option<(&str, u64, Kind)> {
+    let (rest, suffix) = name.rsplit_once('.')?;
+    let kind = Kind::ALL.into_iter().find(|k| k.suffix() == suffix)?;
+    let (base, stamp) = rest.rsplit_once('.')?;
+    Some((base, stamp.parse().ok()?, kind))
+}
+
+fn list_backups(dir: &Path) -> Result<Vec<Backup>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut backups = vec![];
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if let Some((base, stamp, kind)) = parse_backup_name(&name) {
+            backups.push(Backup { name: base.to_string(), stamp, kind, path: path.clone() });
+        }
+    }
+    backups.sort_by_key(|b| b.stamp);
+    Ok(backups)
+}
+
+/// Stamp for a new backup set in `dir`, later than every set already there even within the same millisecond.
+pub fn next_stamp(dir: &Path) -> Result<u64> {
+    let latest = list_backups(dir)?.last().map(|b| b.stamp + 1).unwrap_or(0);
+    Ok(timestamp().max(latest))
+}
+
+/// Temporary file next to `path` that can be renamed over it.
+pub fn temp_path(path: &Path) -> Result<PathBuf> {
+    Ok(dir_of(path).join(format!(".{}.{}.tmp", file_name(path)?, process::id())))
+}
+
+/// Writes to a temporary file next to the target and renames it into place.
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp = temp_path(path)?;
+    let result = File::create(&tmp)
+        .and_then(|mut f| { f.write_all(content)?; f.sync_all() })
+        .and_then(|_| rename(&tmp, path));
+    if result.is_err() {
+        let _ = remove_file(&tmp);
+    }
+    result.map_err(anyhow::Error::from)
+}
+
+/// Copies `path` to `<name>.<stamp>.<suffix>`, never replacing an existing backup.
+fn store(path: &Path, stamp: u64, kind: Kind) -> Result<PathBuf> {
+    let backup = dir_of(path).join(format!("{}.{}.{}", file_name(path)?, stamp, kind.suffix()));
+    if backup.exists() {
+        return Err(anyhow!("backup {} already exists", backup.to_string_lossy()));
+    }
+    match kind {
+        Kind::Created => { File::create_new(&backup)?; },
+        _ => if hard_link(path, &backup).is_err() {
+            copy(path, &backup)?;
+        },
+    }
+    Ok(backup)
+}
+
+/// Removes all but the newest `keep` backups of a file, counting rollback copies separately.
+fn prune(dir: &Path, name: &str, keep: usize) -> Result<()> {
+    let backups = list_backups(dir)?;
+    for undone in [false, true] {
+        let paths: Vec<&PathBuf> = backups.iter()
+            .filter(|b| b.name == name && (b.kind == Kind::Undone) == undone)
+            .map(|b| &b.path)
+            .collect();
+        for old in paths.iter().take(paths.len().saturating_sub(keep)) {
+            remove_file(old)?;
+        }
+    }
+    Ok(())
+}
+
+/// Keeps a copy of an existing file as `<name>.<stamp>.bak`, or marks a missing one as created by this set so that
+/// rollback removes it, pruning all but the newest `keep` backups. Returns the copy, if one was made.
+pub fn backup_file(path: &Path, stamp: u64, keep: usize) -> Result<Option<PathBuf>> {
+    if keep == 0 {
+        return Ok(None);
+    }
+    let backup = match path.is_file() {
+        true => Some(store(path, stamp, Kind::Copy)?),
+        false => {
+            store(path, stamp, Kind::Created)?;
+            None
+        },
+    };
+    prune(&dir_of(path), &file_name(path)?, keep)?;
+    Ok(backup)
+}
+
+/// Undoes the most recent backup set in a directory: files it backed up are restored and files it created are
+/// removed, keeping the current versions as `<name>.<stamp>.undone`. Returns each file and whether it was removed.
+pub fn rollback(dir: &Path, keep: usize) -> Result<Vec<(PathBuf, bool)>> {
+    let backups = list_backups(dir)?;
+    let latest = backups.iter()
+        .rfind(|b| b.kind != Kind::Undone)
+        .map(|b| b.stamp)
+        .ok_or(anyhow!("no backups found in {}", dir.to_string_lossy()))?;
+    let stamp = next_stamp(dir)?;
+    let mut restored = vec![];
+    for backup in backups.iter().filter(|b| b.stamp == latest && b.kind != Kind::Undone) {
+        let path = dir.join(&backup.name);
+        if path.is_file() {
+            store(&path, stamp, Kind::Undone)?;
+        }
+        match backup.kind {
+            Kind::Created => {
+                if path.is_file() {
+                    remove_file(&path)?;
+                }
+                remove_file(&backup.path)?;
+            },
+            _ => rename(&backup.path, &path)?,
+        }
+        restored.push((path, backup.kind == Kind::Created));
+    }
+    for (path, _) in &restored {
+        prune(dir, &file_name(path)?, keep.max(1))?;
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, write};
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Writes `content` to `name` the way a run does, backing up the previous version first.
+    fn run(dir: &Path, files: &[(&str, &str)]) -> u64 {
+        let stamp = next_stamp(dir).unwrap();
+        for (name, content) in files {
+            backup_file(&dir.join(name), stamp, 5).unwrap();
+            write_atomic(&dir.join(name), content.as_bytes()).unwrap();
+        }
+        stamp
+    }
+
+    fn read(dir: &Path, name: &str) -> Option<String> {
+        read_to_string(dir.join(name)).ok()
+    }
+
+    #[test]
+    fn runs_in_the_same_millisecond_keep_every_backup() {
+        let dir = tempdir().unwrap();
+        write(dir.path().join("app.toml"), "original").unwrap();
+        let stamps: Vec<u64> = ["first", "second", "third"].iter().map(|c| run(dir.path(), &[("app.toml", c)])).collect();
+        assert!(stamps.windows(2).all(|s| s[0] < s[1]));
+        let backups: Vec<String> = list_backups(dir.path()).unwrap().iter()
+            .map(|b| read_to_string(&b.path).unwrap())
+            .collect();
+        assert_eq!(backups, ["original", "first", "second"]);
+    }
+
+    #[test]
+    fn existing_backups_are_never_replaced() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("app.toml");
+        write(&path, "original").unwrap();
+        backup_file(&path, 7, 5).unwrap();
+        write_atomic(&path, b"changed").unwrap();
+        assert!(backup_file(&path, 7, 5).is_err());
+        assert_eq!(read(dir.path(), "app.toml.7.bak").as_deref(), Some("original"));
+    }
+
+    #[test]
+    fn prunes_old_backups() {
+        let dir = tempdir().unwrap();
+        for i in 0..4 {
+            write_atomic(&dir.path().join("app.toml"), i.to_string().as_bytes()).unwrap();
+            backup_file(&dir.path().join("app.toml"), i, 2).unwrap();
+        }
+        let stamps: Vec<u64> = list_backups(dir.path()).unwrap().iter().map(|b| b.stamp).collect();
+        assert_eq!(stamps, [2, 3]);
+    }
+
+    #[test]
+    fn rollback_undoes_runs_one_at_a_time() {
+        let dir = tempdir().unwrap();
+        write(dir.path().join("app.toml"), "original").unwrap();
+        run(dir.path(), &[("app.toml", "first"), ("genesis.json", "genesis")]);
+        run(dir.path(), &[("app.toml", "second")]);
+
+        assert_eq!(rollback(dir.path(), 5).unwrap(), [(dir.path().join("app.toml"), false)]);
+        assert_eq!(read(dir.path(), "app.toml").as_deref(), Some("first"));
+        let mut undone = rollback(dir.path(), 5).unwrap();
+        undone.sort();
+        assert_eq!(undone, [(dir.path().join("app.toml"), false), (dir.path().join("genesis.json"), true)]);
+        assert_eq!(read(dir.path(), "app.toml").as_deref(), Some("original"));
+        assert_eq!(read(dir.path(), "genesis.json"), None);
+        assert!(rollback(dir.path(), 5).is_err());
+
+        // every version replaced by a rollback is still on disk
+        let mut kept: Vec<String> = list_backups(dir.path()).unwrap().iter()
+            .filter(|b| b.kind == Kind::Undone)
+            .map(|b| read_to_string(&b.path).unwrap())
+            .collect();
+        kept.sort();
+        assert_eq!(kept, ["first", "genesis", "second"]);
+    }
+}
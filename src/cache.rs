@@ -0,0 +1,152 @@
+use std::env;
+use std::fs::{create_dir_all, read_to_string, remove_file, rename, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use anyhow::Result;
+use log::info;
+
+use crate::backup::write_atomic;
+use crate::genesis::sha256_hex;
+
+/// Cache directory, `$XDG_CACHE_HOME/starsign` or `~/.cache/starsign` unless overridden.
+pub fn cache_dir(cache_dir: &Option<PathBuf>) -> Option<PathBuf> {
+    if let Some(d) = cache_dir {
+        return Some(d.clone());
+    }
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .map(|c| c.join("starsign"))
+}
+
+/// Content-addressed store of unpacked genesis files, with an index from source url to content hash.
+pub struct GenesisCache {
+    dir: PathBuf,
+}
+
+impl GenesisCache {
+    pub fn new(dir: &Path) -> Self {
+        Self { dir: dir.join("genesis") }
+    }
+
+    fn content_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", sha256.trim().to_lowercase()))
+    }
+
+    fn url_path(&self, url: &str) -> PathBuf {
+        self.dir.join("urls").join(sha256_hex(url.as_bytes()))
+    }
+
+    /// Finds a cached genesis by its pinned hash, or by the hash last downloaded from `url`.
+    pub fn lookup(&self, url: &str, sha256: &str) -> Option<PathBuf> {
+        let sha256 = match sha256 {
+            "" => read_to_string(self.url_path(url)).ok()?,
+            s => s.to_string(),
+        };
+        Some(self.content_path(&sha256)).filter(|p| p.is_file())
+    }
+
+    /// Starts caching `source`, which is copied to a temporary file as it is read.
+    pub fn download<R: Read>(self, source: R) -> Result<(TeeReader<R>, CacheDownload)> {
+        create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!(".download.{}.tmp", process::id()));
+        let tee = TeeReader::new(source, File::create(&path)?);
+        Ok((tee, CacheDownload { cache: self, path }))
+    }
+}
+
+/// Genesis download being copied into the cache.
+pub struct CacheDownload {
+    cache: GenesisCache,
+    path: PathBuf,
+}
+
+impl CacheDownload {
+    /// Moves the completed download into the cache under its content hash.
+    pub fn store(self, url: &str, sha256: &str) -> Result<PathBuf> {
+        let path = self.cache.content_path(sha256);
+        rename(&self.path, &path)?;
+        create_dir_all(self.cache.dir.join("urls"))?;
+        write_atomic(&self.cache.url_path(url), sha256.as_bytes())?;
+        info!("cached genesis as {}", path.to_string_lossy());
+        Ok(path)
+    }
+
+    pub fn discard(self) {
+        let _ = remove_file(&self.path);
+    }
+}
+
+/// Copies everything read into a file.
+pub struct TeeReader<R> {
+    inner: R,
+    copy: File,
+}
+
+impl<R: Read> TeeReader<R> {
+    pub fn new(inner: R, copy: File) -> Self {
+        Self { inner, copy }
+    }
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.copy.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, read_dir};
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const URL: &str = "https://example.com/genesis.json.gz";
+    const GENESIS: &[u8] = br#"{"chain_id": "kaiyo-1"}"#;
+
+    fn cache(dir: &Path, url: &str, content: &[u8]) -> PathBuf {
+        let (mut tee, download) = GenesisCache::new(dir).download(content).unwrap();
+        io::copy(&mut tee, &mut io::sink()).unwrap();
+        download.store(url, &sha256_hex(content)).unwrap()
+    }
+
+    #[test]
+    fn looks_up_by_pinned_hash_or_url() {
+        let dir = tempdir().unwrap();
+        let genesis_cache = GenesisCache::new(dir.path());
+        let hash = sha256_hex(GENESIS);
+        assert!(genesis_cache.lookup(URL, "").is_none());
+        assert!(genesis_cache.lookup(URL, &hash).is_none());
+        let path = cache(dir.path(), URL, GENESIS);
+        assert_eq!(read(&path).unwrap(), GENESIS);
+        assert_eq!(genesis_cache.lookup(URL, "").as_ref(), Some(&path));
+        // a pinned hash is found whichever url it came from, and matched case-insensitively
+        assert_eq!(genesis_cache.lookup("https://mirror.example.com/genesis.json", &hash.to_uppercase()).as_ref(), Some(&path));
+        assert!(genesis_cache.lookup("https://mirror.example.com/genesis.json", "").is_none());
+        assert!(genesis_cache.lookup(URL, &"0".repeat(64)).is_none());
+    }
+
+    #[test]
+    fn url_index_follows_the_latest_download() {
+        let dir = tempdir().unwrap();
+        cache(dir.path(), URL, GENESIS);
+        let newer = cache(dir.path(), URL, br#"{"chain_id": "kaiyo-2"}"#);
+        assert_eq!(GenesisCache::new(dir.path()).lookup(URL, ""), Some(newer));
+    }
+
+    #[test]
+    fn discarded_downloads_leave_nothing_behind() {
+        let dir = tempdir().unwrap();
+        let (mut tee, download) = GenesisCache::new(dir.path()).download(GENESIS).unwrap();
+        io::copy(&mut tee, &mut io::sink()).unwrap();
+        download.discard();
+        assert_eq!(read_dir(dir.path().join("genesis")).unwrap().count(), 0);
+        assert!(GenesisCache::new(dir.path()).lookup(URL, &sha256_hex(GENESIS)).is_none());
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use prost::Message;
+
+use crate::config::{SdkVersion, TendermintVersion};
+use crate::rpc::BlockingRpc;
+
+const SDK_MODULE: &str = "github.com/cosmos/cosmos-sdk";
+
+#[derive(Clone, PartialEq, Message)]
+struct Module {
+    #[prost(string, tag = "1")]
+    path: String,
+    #[prost(string, tag = "2")]
+    version: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct VersionInfo {
+    #[prost(string, tag = "2")]
+    app_name: String,
+    #[prost(string, tag = "3")]
+    version: String,
+    #[prost(message, repeated, tag = "7")]
+    build_deps: Vec<Module>,
+    #[prost(string, tag = "8")]
+    cosmos_sdk_version: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GetNodeInfoResponse {
+    #[prost(message, optional, tag = "2")]
+    application_version: Option<VersionInfo>,
+}
+
+/// Versions reported by a running node.
+#[derive(Clone, Copy)]
+pub struct NodeVersions {
+    pub tendermint: Option<TendermintVersion>,
+    pub sdk: Option<SdkVersion>,
+}
+
+/// Major and minor version of a release string such as `v0.47.5` or `0.38.0-rc3`.
+fn release(version: &str) -> Option<(u64, u64)> {
+    let mut parts = version.trim().trim_start_matches('v').split(['.', '-', '+']);
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+fn tendermint_version(version: &str) -> Option<TendermintVersion> {
+    match release(version)? {
+        (0, minor) if minor <= 34 => Some(TendermintVersion::V034),
+        (0, 37) => Some(TendermintVersion::V037),
+        (0, minor) if minor >= 38 => Some(TendermintVersion::V038),
+        _ => None,
+    }
+}
+
+fn sdk_version(version: &str) -> Option<SdkVersion> {
+    match release(version)? {
+        (0, minor) if minor <= 45 => Some(SdkVersion::V045),
+        (0, 46) => Some(SdkVersion::V046),
+        (0, 47) => Some(SdkVersion::V047),
+        (0, minor) if minor >= 50 => Some(SdkVersion::V050),
+        _ => None,
+    }
+}
+
+/// Reads the Cosmos SDK version from the node info query, falling back to the app's build dependencies.
+fn query_sdk_version(rpc: &BlockingRpc) -> Result<String> {
+    let response = rpc.abci_query("/cosmos.base.tendermint.v1beta1.Service/GetNodeInfo", vec![])?;
+    let app = GetNodeInfoResponse::decode(response.as_slice())?
+        .application_version
+        .ok_or(anyhow!("node info has no application version"))?;
+    info!("node runs {} {}", app.app_name, app.version);
+    Some(app.cosmos_sdk_version)
+        .filter(|v| !v.is_empty())
+        .or_else(|| app.build_deps.into_iter().find(|m| m.path == SDK_MODULE).map(|m| m.version))
+        .ok_or(anyhow!("node info does not report a cosmos sdk version"))
+}
+
+/// Detects which config layouts match the node behind `rpc`.
+pub fn node_versions(rpc: &BlockingRpc) -> Result<NodeVersions> {
+    let reported = rpc.status()?.node_info.version.to_string();
+    let tendermint = tendermint_version(&reported);
+    match tendermint {
+        Some(v) => info!("detected tendermint {}, using the {} config.toml layout", reported, v),
+        None => warn!("no config.toml layout for tendermint {}", reported),
+    }
+    let sdk = match query_sdk_version(rpc) {
+        Ok(reported) => {
+            let sdk = sdk_version(&reported);
+            match sdk {
+                Some(v) => info!("detected cosmos sdk {}, using the {} app.toml layout", reported, v),
+                None => warn!("no app.toml layout for cosmos sdk {}", reported),
+            }
+            sdk
+        },
+        Err(err) => {
+            warn!("could not detect the cosmos sdk version: {}", err);
+            None
+        },
+    };
+    Ok(NodeVersions { tendermint, sdk })
+}
+
+/// Detects versions from the first of `servers` that answers.
+pub fn detect_versions(servers: &[String]) -> Result<NodeVersions> {
+    let mut last_err = anyhow!("no rpc server to detect versions from");
+    for server in servers {
+        match BlockingRpc::from_url(server).and_then(|rpc| node_versions(&rpc)) {
+            Ok(versions) => return Ok(versions),
+            Err(err) => {
+                warn!("could not detect versions from {}: {}", server, err);
+                last_err = err;
+            },
+        }
+    }
+    Err(last_err)
+}
@@ -0,0 +1,286 @@
+use std::fs::{read_to_string, remove_file, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{info, warn};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
+
+use crate::backup::write_atomic;
+use self::Failure::{Fatal, Retry};
+
+pub struct DownloadOptions {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub retries: u32,
+    pub progress: bool,
+    /// Where partial downloads are kept so an interrupted download can resume.
+    pub partial_dir: PathBuf,
+}
+
+enum Failure {
+    /// Worth retrying, such as timeouts, dropped connections and server errors.
+    Retry(anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+fn fatal(err: impl Into<anyhow::Error>) -> Failure {
+    Fatal(err.into())
+}
+
+fn header(response: &Response, name: reqwest::header::HeaderName) -> Option<String> {
+    response.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string)
+}
+
+fn progress_bar(options: &DownloadOptions, total: Option<u64>, done: u64) -> ProgressBar {
+    if !options.progress {
+        return ProgressBar::hidden();
+    }
+    let bar = match total {
+        Some(t) => ProgressBar::with_draw_target(Some(t), ProgressDrawTarget::stderr()).with_style(
+            ProgressStyle::with_template("{bytes}/{total_bytes} [{wide_bar}] {bytes_per_sec}, {eta}")
+                .unwrap_or_else(|_| ProgressStyle::default_bar()),
+        ),
+        None => ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr()).with_style(
+            ProgressStyle::with_template("{spinner} {bytes} {bytes_per_sec}")
+                .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+        ),
+    };
+    bar.set_position(done);
+    bar
+}
+
+/// Discards a partial download, which cannot be resumed.
+fn discard(part: &Path) -> Result<(), Failure> {
+    for path in [part.to_path_buf(), part.with_extension("validator")] {
+        match remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(fatal(err)),
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Makes one attempt at fetching the rest of `url` into `part`, returning the Content-Type.
+fn attempt(client: &Client, url: &str, part: &Path, options: &DownloadOptions) -> Result<Option<String>, Failure> {
+    let validator_path = part.with_extension("validator");
+    // only resume when the server can tell whether the partial is still the same content
+    let validator = read_to_string(&validator_path).ok();
+    if validator.is_none() {
+        discard(part)?;
+    }
+    let existing = part.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut request = client.get(url);
+    if let Some(v) = validator.filter(|_| existing > 0) {
+        request = request.header(RANGE, format!("bytes={}-", existing)).header(IF_RANGE, v);
+    }
+    let mut response = request.send().map_err(|err| Retry(err.into()))?;
+    let status = response.status();
+    if status == StatusCode::RANGE_NOT_SATISFIABLE && existing > 0 {
+        discard(part)?;
+        return Err(Retry(anyhow!("server cannot resume at {} bytes, restarting", existing)));
+    }
+    let resuming = status == StatusCode::PARTIAL_CONTENT;
+    let range_start = header(&response, CONTENT_RANGE)
+        .and_then(|r| r.strip_prefix("bytes ")?.split_once('-')?.0.parse::<u64>().ok());
+    if resuming && range_start != Some(existing) {
+        discard(part)?;
+        return Err(Retry(anyhow!("server resumed at {:?} instead of {} bytes, restarting", range_start, existing)));
+    }
+    let start = if resuming { existing } else { 0 };
+    if resuming {
+        info!("resuming download at {} bytes", existing);
+    }
+    // weak etags cannot validate a range, fall back to the modification time
+    match header(&response, ETAG).filter(|e| !e.starts_with("W/")).or_else(|| header(&response, LAST_MODIFIED)) {
+        Some(v) => write_atomic(&validator_path, v.as_bytes()).map_err(Fatal)?,
+        None => { let _ = remove_file(&validator_path); },
+    }
+    let total = match header(&response, CONTENT_RANGE) {
+        Some(range) if resuming => range.rsplit('/').next().and_then(|t| t.parse().ok()),
+        _ => response.content_length().map(|l| l + start),
+    };
+    let mut file = File::options().create(true).append(resuming).write(true).truncate(!resuming).open(part)
+        .map_err(fatal)?;
+    let bar = progress_bar(options, total, start);
+    let mut buf = vec![0u8; 1 << 16];
+    let mut done = start;
+    loop {
+        let n = match response.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                bar.abandon();
+                return Err(Retry(err.into()));
+            },
+        };
+        file.write_all(&buf[..n]).map_err(fatal)?;
+        done += n as u64;
+        bar.set_position(done);
+    }
+    bar.finish_and_clear();
+    file.sync_all().map_err(fatal)?;
+    if let Some(t) = total.filter(|t| *t != done) {
+        return Err(Retry(anyhow!("download ended at {} of {} bytes", done, t)));
+    }
+    Ok(header(&response, CONTENT_TYPE))
+}
+
+/// Downloads `url` into `part` with retries, resuming from whatever a previous attempt left behind.
+pub fn download(url: &str, part: &Path, options: &DownloadOptions) -> Result<Option<String>> {
+    let client = Client::builder()
+        .connect_timeout(options.connect_timeout)
+        .timeout(options.read_timeout)
+        .build()?;
+    let mut backoff = Duration::from_millis(500);
+    let mut failures = 0;
+    loop {
+        match attempt(&client, url, part, options) {
+            Ok(content_type) => {
+                let _ = remove_file(part.with_extension("validator"));
+                return Ok(content_type);
+            },
+            Err(Fatal(err)) => return Err(anyhow!("download of {} failed: {}", url, err)),
+            Err(Retry(err)) => {
+                failures += 1;
+                if failures > options.retries {
+                    return Err(anyhow!("download of {} failed after {} attempts: {}", url, failures, err));
+                }
+                warn!("download of {} failed, retrying in {:?}: {}", url, backoff, err);
+                sleep(backoff);
+                backoff = (backoff * 2).min(Duration::from_secs(30));
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, write};
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const BODY: &[u8] = b"{\"chain_id\": \"kaiyo-1\", \"genesis_time\": \"2023-01-01T00:00:00Z\"}";
+
+    /// Serves `BODY` with range support like a static file server, logging each request's Range header.
+    struct MockServer {
+        url: String,
+        ranges: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl MockServer {
+        fn serve(etag: Option<&'static str>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/genesis.json", listener.local_addr().unwrap());
+            let ranges = Arc::new(Mutex::new(vec![]));
+            let log = ranges.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(&stream);
+                    let (mut range, mut if_range) = (None, None);
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            match name.to_ascii_lowercase().as_str() {
+                                "range" => range = Some(value.trim().to_string()),
+                                "if-range" => if_range = Some(value.trim().to_string()),
+                                _ => {},
+                            }
+                        }
+                    }
+                    log.lock().unwrap().push(range.clone());
+                    let start = range.as_deref()
+                        .filter(|_| if_range.is_none() || if_range.as_deref() == etag)
+                        .and_then(|r| r.strip_prefix("bytes=")?.strip_suffix('-')?.parse::<usize>().ok());
+                    let etag_header = etag.map(|e| format!("ETag: {}\r\n", e)).unwrap_or_default();
+                    let head = match start {
+                        Some(s) if s >= BODY.len() => {
+                            write!(stream, "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+                            continue;
+                        },
+                        Some(s) => format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n", s, BODY.len() - 1, BODY.len(),
+                        ),
+                        None => "HTTP/1.1 200 OK\r\n".to_string(),
+                    };
+                    let body = &BODY[start.unwrap_or(0)..];
+                    write!(stream, "{}{}Content-Length: {}\r\nConnection: close\r\n\r\n", head, etag_header, body.len()).unwrap();
+                    stream.write_all(body).unwrap();
+                }
+            });
+            Self { url, ranges }
+        }
+
+        fn ranges(&self) -> Vec<Option<String>> {
+            self.ranges.lock().unwrap().clone()
+        }
+    }
+
+    fn options(dir: &Path) -> DownloadOptions {
+        DownloadOptions {
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(5),
+            retries: 2,
+            progress: false,
+            partial_dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Downloads from `server` over a partial file left by an earlier attempt.
+    fn resume(server: &MockServer, partial: &[u8], validator: Option<&str>) -> Vec<u8> {
+        let dir = tempdir().unwrap();
+        let part = dir.path().join("genesis.part");
+        write(&part, partial).unwrap();
+        if let Some(v) = validator {
+            write(part.with_extension("validator"), v).unwrap();
+        }
+        download(&server.url, &part, &options(dir.path())).unwrap();
+        assert!(!part.with_extension("validator").exists());
+        read(&part).unwrap()
+    }
+
+    #[test]
+    fn resumes_a_partial_with_a_matching_validator() {
+        let server = MockServer::serve(Some("\"v1\""));
+        assert_eq!(resume(&server, &BODY[..10], Some("\"v1\"")), BODY);
+        assert_eq!(server.ranges(), [Some("bytes=10-".to_string())]);
+    }
+
+    #[test]
+    fn discards_a_partial_without_a_validator() {
+        // the partial is from other content, resuming it would splice the two
+        let server = MockServer::serve(None);
+        assert_eq!(resume(&server, b"{\"chain_id\": \"harpoon-4\"", None), BODY);
+        assert_eq!(server.ranges(), [None]);
+    }
+
+    #[test]
+    fn downloads_changed_content_again() {
+        let server = MockServer::serve(Some("\"v2\""));
+        assert_eq!(resume(&server, b"{\"chain_id\": \"harpoon-4\"", Some("\"v1\"")), BODY);
+    }
+
+    #[test]
+    fn restarts_when_the_range_is_not_satisfiable() {
+        let server = MockServer::serve(Some("\"v1\""));
+        assert_eq!(resume(&server, &[BODY, b"trailing"].concat(), Some("\"v1\"")), BODY);
+        assert_eq!(server.ranges(), [Some(format!("bytes={}-", BODY.len() + 8)), None]);
+    }
+}
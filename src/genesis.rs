@@ -0,0 +1,497 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use json_patch::merge;
+use log::warn;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use tendermint::Time;
+
+use crate::overrides::{Overrides, SetOverride, StreamPatch};
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Checks a computed genesis sha256 against `expected` unless that is empty.
+pub fn verify_sha256_hex(hash: &str, expected: &str) -> Result<String> {
+    if !expected.is_empty() && !hash.eq_ignore_ascii_case(expected.trim()) {
+        return Err(anyhow!("genesis sha256 is {}, expected {}", hash, expected.trim()));
+    }
+    Ok(hash.to_string())
+}
+
+/// Passes reads through while hashing everything read.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, hasher: Sha256::new() }
+    }
+
+    pub fn sha256_hex(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Streams genesis to `path`, applying `overrides`, and returns the sha256 of the unpatched input.
+pub fn write_genesis(reader: impl Read, path: &Path, overrides: &Overrides) -> Result<String> {
+    let mut reader = HashingReader::new(reader);
+    let mut file = File::create(path)?;
+    if overrides.is_empty() {
+        io::copy(&mut reader, &mut file)?;
+    } else if let Some(p) = overrides.as_stream_patch() {
+        patch_genesis(&mut reader, &mut file, &p)?;
+    } else {
+        warn!("loading genesis into memory to apply --patch operations");
+        let mut genesis: Value = serde_json::from_reader(BufReader::new(&mut reader))?;
+        overrides.apply(&mut genesis)?;
+        let mut out = BufWriter::new(&mut file);
+        serde_json::to_writer_pretty(&mut out, &genesis)?;
+        out.flush()?;
+        io::copy(&mut reader, &mut io::sink())?;
+    }
+    file.sync_all()?;
+    Ok(reader.sha256_hex())
+}
+
+/// Hashes a file without loading it into memory.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut reader = HashingReader::new(File::open(path)?);
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(reader.sha256_hex())
+}
+
+/// The value a merge patch produces when applied to a missing or non-object value.
+fn merged(patch: &Value) -> Value {
+    let mut value = Value::Null;
+    merge(&mut value, patch);
+    value
+}
+
+/// Streaming JSON reader that copies or skips values without materializing them.
+struct JsonStream<'a, R> {
+    inner: R,
+    /// Keys leading to the value being patched.
+    path: Vec<String>,
+    sets: &'a [SetOverride],
+}
+
+impl<R: BufRead> JsonStream<'_, R> {
+    fn peek(&mut self) -> Result<Option<u8>> {
+        Ok(self.inner.fill_buf()?.first().copied())
+    }
+
+    fn next(&mut self) -> Result<u8> {
+        let b = self.peek()?.ok_or(anyhow!("unexpected end of genesis json"))?;
+        self.inner.consume(1);
+        Ok(b)
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<()> {
+        let b = self.next()?;
+        if b != expected {
+            return Err(anyhow!("invalid genesis json: expected '{}', found '{}'", expected as char, b as char));
+        }
+        Ok(())
+    }
+
+    fn whitespace(&mut self) -> Result<Vec<u8>> {
+        let mut ws = vec![];
+        while let Some(b) = self.peek()? {
+            if !b.is_ascii_whitespace() {
+                break;
+            }
+            ws.push(b);
+            self.inner.consume(1);
+        }
+        Ok(ws)
+    }
+
+    /// Copies the next value to `out`, or skips it when `out` is `None`.
+    fn scan_value(&mut self, mut out: Option<&mut dyn Write>) -> Result<()> {
+        let first = self.peek()?.ok_or(anyhow!("unexpected end of genesis json"))?;
+        let scalar = !matches!(first, b'{' | b'[' | b'"');
+        let (mut depth, mut in_string, mut escaped, mut started) = (0usize, false, false, false);
+        loop {
+            let buf = self.inner.fill_buf()?;
+            if buf.is_empty() {
+                if scalar && started {
+                    return Ok(());
+                }
+                return Err(anyhow!("unexpected end of genesis json"));
+            }
+            let mut end = None;
+            for (i, &b) in buf.iter().enumerate() {
+                if scalar {
+                    if matches!(b, b',' | b'}' | b']') || b.is_ascii_whitespace() {
+                        end = Some(i);
+                        break;
+                    }
+                    started = true;
+                    continue;
+                }
+                if in_string {
+                    if escaped {
+                        escaped = false;
+                    } else if b == b'\\' {
+                        escaped = true;
+                    } else if b == b'"' {
+                        in_string = false;
+                        if depth == 0 {
+                            end = Some(i + 1);
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                match b {
+                    b'"' => in_string = true,
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            end = Some(i + 1);
+                            break;
+                        }
+                    },
+                    _ => {},
+                }
+            }
+            let n = end.unwrap_or(buf.len());
+            if let Some(w) = out.as_mut() {
+                w.write_all(&buf[..n])?;
+            }
+            self.inner.consume(n);
+            if end.is_some() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_key(&mut self) -> Result<(Vec<u8>, String)> {
+        let mut raw = vec![];
+        self.scan_value(Some(&mut raw))?;
+        let key = serde_json::from_slice(&raw).map_err(|err| anyhow!("invalid genesis json key: {}", err))?;
+        Ok((raw, key))
+    }
+
+    /// Applies a merge patch to the next value, copying everything the patch does not touch verbatim.
+    fn patch_value(&mut self, out: &mut dyn Write, patch: &Value) -> Result<()> {
+        match patch {
+            Value::Object(members) if self.peek()? == Some(b'{') => self.patch_object(out, members),
+            _ => {
+                let replaces_string = self.peek()? == Some(b'"');
+                self.scan_value(None)?;
+                let set = self.sets.iter().rev().find(|s| s.path == self.path);
+                let value = match set {
+                    Some(s) if replaces_string => s.typed_value(Some(&Value::String(String::new()))),
+                    _ => merged(patch),
+                };
+                serde_json::to_writer(out, &value)?;
+                Ok(())
+            },
+        }
+    }
+
+    fn patch_object(&mut self, out: &mut dyn Write, patch: &Map<String, Value>) -> Result<()> {
+        self.expect(b'{')?;
+        out.write_all(b"{")?;
+        let mut seen = HashSet::new();
+        let mut first = true;
+        let mut indent = vec![];
+        let mut trailing = self.whitespace()?;
+        if self.peek()? == Some(b'}') {
+            self.next()?;
+        } else {
+            loop {
+                let leading = trailing;
+                let (raw_key, key) = self.read_key()?;
+                let before_colon = self.whitespace()?;
+                self.expect(b':')?;
+                let after_colon = self.whitespace()?;
+                let member_patch = patch.get(&key);
+                if member_patch != Some(&Value::Null) {
+                    if !first {
+                        out.write_all(b",")?;
+                    }
+                    first = false;
+                    out.write_all(&leading)?;
+                    out.write_all(&raw_key)?;
+                    out.write_all(&before_colon)?;
+                    out.write_all(b":")?;
+                    out.write_all(&after_colon)?;
+                }
+                match member_patch {
+                    Some(Value::Null) => self.scan_value(None)?,
+                    Some(p) => {
+                        self.path.push(key.clone());
+                        self.patch_value(out, p)?;
+                        self.path.pop();
+                    },
+                    None => self.scan_value(Some(out))?,
+                }
+                seen.insert(key);
+                indent = leading;
+                trailing = self.whitespace()?;
+                match self.next()? {
+                    b'}' => break,
+                    b',' => trailing = self.whitespace()?,
+                    b => return Err(anyhow!("invalid genesis json: expected ',' or '}}', found '{}'", b as char)),
+                }
+            }
+        }
+        for (key, value) in patch.iter().filter(|(k, v)| !seen.contains(*k) && !v.is_null()) {
+            if !first {
+                out.write_all(b",")?;
+            }
+            first = false;
+            out.write_all(&indent)?;
+            serde_json::to_writer(&mut *out, key)?;
+            out.write_all(b": ")?;
+            serde_json::to_writer(&mut *out, &merged(value))?;
+        }
+        out.write_all(&trailing)?;
+        out.write_all(b"}")?;
+        Ok(())
+    }
+}
+
+/// Applies a merge patch to a genesis stream, rewriting only the patched subtrees.
+pub fn patch_genesis(reader: impl Read, writer: impl Write, patch: &StreamPatch) -> Result<()> {
+    let mut stream = JsonStream { inner: BufReader::with_capacity(1 << 16, reader), path: vec![], sets: &patch.sets };
+    let mut out = BufWriter::with_capacity(1 << 16, writer);
+    let leading = stream.whitespace()?;
+    out.write_all(&leading)?;
+    stream.patch_value(&mut out, &patch.merge)?;
+    let trailing = stream.whitespace()?;
+    if stream.peek()?.is_some() {
+        return Err(anyhow!("invalid genesis json: trailing data"));
+    }
+    out.write_all(&trailing)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Problem found in a genesis file.
+#[derive(Debug)]
+pub enum GenesisIssue {
+    InvalidJson(String),
+    ChainIdMismatch { expected: String, found: String },
+    InvalidGenesisTime(String),
+    InvalidInitialHeight(String),
+    InvalidConsensusParams(String),
+    MissingModule(String),
+}
+
+impl fmt::Display for GenesisIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenesisIssue::InvalidJson(err) => write!(f, "invalid json: {}", err),
+            GenesisIssue::ChainIdMismatch { expected, found } => write!(f, "chain_id is {}, expected {}", found, expected),
+            GenesisIssue::InvalidGenesisTime(time) => write!(f, "invalid genesis_time {}", time),
+            GenesisIssue::InvalidInitialHeight(height) => write!(f, "invalid initial_height {}", height),
+            GenesisIssue::InvalidConsensusParams(err) => write!(f, "invalid consensus_params: {}", err),
+            GenesisIssue::MissingModule(module) => write!(f, "app_state has no {} module", module),
+        }
+    }
+}
+
+/// Top-level genesis fields, skipping the contents of `app_state` so large files are not held in memory.
+#[derive(Deserialize)]
+struct GenesisSummary {
+    #[serde(default)]
+    chain_id: String,
+    #[serde(default)]
+    genesis_time: String,
+    #[serde(default)]
+    initial_height: Option<Value>,
+    #[serde(default)]
+    consensus_params: Option<Value>,
+    #[serde(default)]
+    app_state: Option<HashMap<String, IgnoredAny>>,
+}
+
+/// Integers in genesis are usually strings, but older files use plain numbers.
+pub(crate) fn int_value(value: Option<&Value>) -> Option<i64> {
+    match value? {
+        Value::String(s) => s.parse().ok(),
+        Value::Number(n) => n.as_i64(),
+        _ => None,
+    }
+}
+
+fn check_consensus_params(params: &Value) -> Option<String> {
+    for section in ["block", "evidence", "validator"] {
+        if !params.get(section).is_some_and(|s| s.is_object()) {
+            return Some(format!("missing {}", section));
+        }
+    }
+    if !int_value(params["block"].get("max_bytes")).is_some_and(|n| n > 0 || n == -1) {
+        return Some("block.max_bytes must be positive or -1".to_string());
+    }
+    if int_value(params["block"].get("max_gas")).is_none_or(|n| n < -1) {
+        return Some("block.max_gas must be at least -1".to_string());
+    }
+    match params["validator"].get("pub_key_types").and_then(|t| t.as_array()) {
+        Some(types) if !types.is_empty() => None,
+        _ => Some("validator.pub_key_types is empty".to_string()),
+    }
+}
+
+/// Checks a genesis file is usable for `chain_id` and contains the `modules` the chain needs.
+pub fn validate_genesis(path: &Path, chain_id: Option<&str>, modules: &[&str]) -> Result<Vec<GenesisIssue>> {
+    let summary: GenesisSummary = match serde_json::from_reader(BufReader::with_capacity(1 << 16, File::open(path)?)) {
+        Ok(s) => s,
+        Err(err) => return Ok(vec![GenesisIssue::InvalidJson(err.to_string())]),
+    };
+    let mut issues = vec![];
+    if let Some(expected) = chain_id.filter(|c| *c != summary.chain_id) {
+        issues.push(GenesisIssue::ChainIdMismatch { expected: expected.to_string(), found: summary.chain_id.clone() });
+    }
+    if Time::parse_from_rfc3339(&summary.genesis_time).is_err() {
+        issues.push(GenesisIssue::InvalidGenesisTime(summary.genesis_time.clone()));
+    }
+    if let Some(height) = summary.initial_height.as_ref().filter(|h| int_value(Some(h)).is_none_or(|n| n < 0)) {
+        issues.push(GenesisIssue::InvalidInitialHeight(height.to_string()));
+    }
+    match &summary.consensus_params {
+        Some(params) => {
+            if let Some(err) = check_consensus_params(params) {
+                issues.push(GenesisIssue::InvalidConsensusParams(err));
+            }
+        },
+        None => issues.push(GenesisIssue::InvalidConsensusParams("missing".to_string())),
+    }
+    let app_state = summary.app_state.unwrap_or_default();
+    for module in modules.iter().filter(|m| !app_state.contains_key(**m)) {
+        issues.push(GenesisIssue::MissingModule(module.to_string()));
+    }
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::from_str;
+
+    use super::*;
+
+    fn overrides(custom: &[&str], set: &[&str]) -> Overrides {
+        let custom: Vec<String> = custom.iter().map(|c| c.to_string()).collect();
+        let set: Vec<String> = set.iter().map(|s| s.to_string()).collect();
+        Overrides::parse(&custom, &[], &set).unwrap()
+    }
+
+    /// Streams `input` through the patch and checks the result matches patching the parsed document.
+    fn stream(input: &str, custom: &[&str], set: &[&str]) -> String {
+        let overrides = overrides(custom, set);
+        let patch = overrides.as_stream_patch().expect("overrides should stream");
+        let mut out = vec![];
+        patch_genesis(input.as_bytes(), &mut out, &patch).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let mut expected: Value = from_str(input).unwrap();
+        overrides.apply(&mut expected).unwrap();
+        assert_eq!(from_str::<Value>(&out).unwrap(), expected, "streamed {}", out);
+        out
+    }
+
+    #[test]
+    fn copies_untouched_members_verbatim() {
+        let input = "{\n  \"chain_id\" : \"kaiyo-1\",\n  \"app_state\": {\"bank\": {\"supply\": [ 1, 2 ]}, \"wasm\": {}}\n}\n";
+        let out = stream(input, &[r#"{"app_state": {"wasm": {"params": {"code_upload_access": "Nobody"}}}}"#], &[]);
+        assert!(out.starts_with("{\n  \"chain_id\" : \"kaiyo-1\",\n  \"app_state\": {\"bank\": {\"supply\": [ 1, 2 ]}, "), "{}", out);
+        assert!(out.ends_with("}\n"), "{}", out);
+    }
+
+    #[test]
+    fn handles_escaped_quotes_in_keys_and_strings() {
+        let input = r#"{"a\"b": "x\"}\\", "c": {"d\"": "{[\"", "e": "\\\""}, "f\\": 1}"#;
+        stream(input, &[r#"{"c": {"d\"": 2, "new\"": "v\"}"}, "f\\": null}"#], &[]);
+        stream(input, &[r#"{"a\"b": {"nested": true}}"#], &[]);
+    }
+
+    #[test]
+    fn deletes_first_and_last_members() {
+        let input = "{\n  \"first\": 1,\n  \"mid\": {\"x\": [1, {\"y\": 2}]},\n  \"last\": \"3\"\n}";
+        let out = stream(input, &[r#"{"first": null, "last": null}"#], &[]);
+        assert_eq!(out, "{\n  \"mid\": {\"x\": [1, {\"y\": 2}]}\n}");
+        stream(input, &[r#"{"first": null, "mid": null, "last": null}"#], &[]);
+        stream(input, &[r#"{"missing": null}"#], &[]);
+    }
+
+    #[test]
+    fn patches_into_empty_objects_and_null() {
+        let input = r#"{"empty": {}, "null": null, "scalar": 5, "array": [1]}"#;
+        stream(input, &[r#"{"empty": {"a": 1, "b": null}, "null": {"c": {"d": 2}}, "scalar": {"e": 3}, "array": {"f": []}}"#], &[]);
+        stream("{ }", &[r#"{"a": {"b": null, "c": "d"}}"#], &[]);
+    }
+
+    #[test]
+    fn patches_scalar_and_array_top_levels() {
+        for input in ["5", "\"genesis\"", "null", "[1, {\"a\": 2}]", " true \n"] {
+            stream(input, &[r#"{"a": {"b": 1}}"#], &[]);
+        }
+    }
+
+    #[test]
+    fn composes_repeated_overrides() {
+        let input = r#"{"a": {"b": 1, "c": 2}, "d": "e"}"#;
+        stream(input, &[r#"{"a": {"b": null}}"#, r#"{"a": {"c": 3, "b": 4}, "d": null}"#, r#"{"d": "f"}"#], &[]);
+    }
+
+    #[test]
+    fn loads_overrides_that_cannot_stream() {
+        let input = r#"{"a": {"z": 0}, "b": [1, 2]}"#;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("genesis.json");
+        let cases = [
+            overrides(&[r#"{"a": 1}"#, r#"{"a": {"x": null, "y": 2}}"#], &[]),
+            overrides(&[], &["b.1=3"]),
+            Overrides::parse(&[], &[r#"[{"op": "add", "path": "/b/-", "value": 4}]"#.to_string()], &[]).unwrap(),
+        ];
+        for overrides in cases {
+            assert!(overrides.as_stream_patch().is_none());
+            let hash = write_genesis(input.as_bytes(), &path, &overrides).unwrap();
+            assert_eq!(hash, sha256_hex(input.as_bytes()));
+            let mut expected: Value = from_str(input).unwrap();
+            overrides.apply(&mut expected).unwrap();
+            assert_eq!(from_str::<Value>(&std::fs::read_to_string(&path).unwrap()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn set_keeps_strings_as_strings() {
+        let input = r#"{"chain_id": "kaiyo-1", "initial_height": "1", "params": {"max": 10, "enabled": "true"}}"#;
+        let out = stream(input, &[], &["initial_height=5", "params.max=20", "params.enabled=false", "params.new=7"]);
+        let out: Value = from_str(&out).unwrap();
+        assert_eq!(out["initial_height"], Value::String("5".to_string()));
+        assert_eq!(out["params"]["max"], Value::from(20));
+        assert_eq!(out["params"]["enabled"], Value::String("false".to_string()));
+        assert_eq!(out["params"]["new"], Value::from(7));
+    }
+
+    #[test]
+    fn sha256_mismatch_is_an_error() {
+        let hash = sha256_hex(b"{}");
+        assert!(verify_sha256_hex(&hash, "").is_ok());
+        assert!(verify_sha256_hex(&hash, &format!(" {} ", hash.to_uppercase())).is_ok());
+        assert!(verify_sha256_hex(&hash, &"0".repeat(64)).is_err());
+    }
+}
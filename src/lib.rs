@@ -1,2 +1,17 @@
+pub mod archive;
+pub mod backup;
+pub mod cache;
 pub mod config;
+pub mod detect;
+pub mod download;
+pub mod genesis;
+pub mod light;
+pub mod overrides;
+pub mod peers;
+pub mod probe;
+pub mod profile;
+pub mod registry;
+pub mod role;
 pub mod rpc;
+pub mod statesync;
+pub mod update;
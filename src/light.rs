@@ -0,0 +1,512 @@
+use std::collections::HashSet;
+use std::io::{BufReader, Read};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use log::info;
+use serde::Deserialize;
+use serde_json::Value;
+use tendermint::block::{CommitSig, Height};
+use tendermint::block::signed_header::SignedHeader;
+use tendermint::chain;
+use tendermint::validator::{Info, Set};
+use tendermint::vote::{SignedVote, Type, ValidatorIndex, Vote};
+use tendermint::Time;
+
+use crate::genesis::int_value;
+use crate::rpc::BlockingRpc;
+
+/// Validated header, commit and validator sets for a single height.
+pub struct LightBlock {
+    pub signed_header: SignedHeader,
+    pub validators: Set,
+    pub next_validators: Set,
+}
+
+/// Starting point for light-client verification.
+pub enum TrustRoot {
+    /// Operator-supplied header hash at a height.
+    Header { height: u64, hash: String },
+    /// Initial validator set from a genesis file.
+    Genesis { chain_id: String, height: u64, validators: Vec<Info> },
+}
+
+/// The genesis fields a trust root needs, skipping `app_state` and everything else without holding it in memory.
+#[derive(Deserialize)]
+struct GenesisRoot {
+    chain_id: String,
+    #[serde(default)]
+    initial_height: Option<Value>,
+    #[serde(default)]
+    validators: Vec<Info>,
+}
+
+impl FromStr for TrustRoot {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (height, hash) = s.split_once(':').ok_or(anyhow!("trust root must be <height>:<hash>"))?;
+        Ok(TrustRoot::Header { height: height.parse()?, hash: hash.to_uppercase() })
+    }
+}
+
+impl TrustRoot {
+    pub fn from_genesis(genesis: impl Read) -> Result<Self> {
+        let root: GenesisRoot = serde_json::from_reader(BufReader::with_capacity(1 << 16, genesis))
+            .map_err(|err| anyhow!("invalid genesis: {}", err))?;
+        if root.validators.is_empty() {
+            return Err(anyhow!(
+                "genesis lists no validators (cosmos sdk chains create them from gentxs), use --statesync-trust-root",
+            ));
+        }
+        let height = match &root.initial_height {
+            Some(h) => int_value(Some(h))
+                .and_then(|h| u64::try_from(h).ok())
+                .ok_or(anyhow!("invalid genesis initial_height {}", h))?,
+            None => 1,
+        };
+        Ok(TrustRoot::Genesis { chain_id: root.chain_id, height, validators: root.validators })
+    }
+
+    fn height(&self) -> u64 {
+        match self {
+            TrustRoot::Header { height, .. } => *height,
+            TrustRoot::Genesis { height, .. } => *height,
+        }
+    }
+}
+
+fn to_height(height: u64) -> Result<u32> {
+    height.try_into().map_err(|_| anyhow!("height {} out of range", height))
+}
+
+/// Verifies headers against validator signatures, skipping ahead where the trusted validators allow.
+pub struct LightClient<'a> {
+    rpc: &'a BlockingRpc,
+    trust_period: Duration,
+}
+
+impl<'a> LightClient<'a> {
+    pub fn new(rpc: &'a BlockingRpc, trust_period: Duration) -> Self {
+        Self { rpc, trust_period }
+    }
+
+    fn fetch(&self, height: u64) -> Result<LightBlock> {
+        let signed_header = self.rpc.commit(to_height(height)?)?.signed_header;
+        let validators = Set::without_proposer(self.rpc.validators(to_height(height)?)?.validators);
+        let next_validators = Set::without_proposer(self.rpc.validators(to_height(height + 1)?)?.validators);
+        let block = LightBlock { signed_header, validators, next_validators };
+        validate(&block, height)?;
+        Ok(block)
+    }
+
+    fn check_trusting_period(&self, trusted: &LightBlock) -> Result<()> {
+        let header_time = trusted.signed_header.header.time;
+        let age = Time::now().duration_since(header_time).unwrap_or_default();
+        if age >= self.trust_period {
+            return Err(anyhow!(
+                "trusted header at height {} is older than the trust period", trusted.signed_header.header.height,
+            ));
+        }
+        Ok(())
+    }
+
+    fn trusted_root(&self, root: &TrustRoot) -> Result<LightBlock> {
+        let block = self.fetch(root.height())?;
+        let header = &block.signed_header.header;
+        match root {
+            TrustRoot::Header { hash, .. } => {
+                if header.hash().to_string() != *hash {
+                    return Err(anyhow!("header hash at trust root height {} is {}, not {}", root.height(), header.hash(), hash));
+                }
+            },
+            TrustRoot::Genesis { chain_id, validators, .. } => {
+                if header.chain_id.as_str() != chain_id {
+                    return Err(anyhow!("rpc chain id {} does not match genesis chain id {}", header.chain_id, chain_id));
+                }
+                if Set::without_proposer(validators.clone()).hash() != header.validators_hash {
+                    return Err(anyhow!("genesis validators do not match the validator set at height {}", root.height()));
+                }
+                verify_commit(&block, &block.validators, 2, 3)?;
+            },
+        }
+        self.check_trusting_period(&block)?;
+        Ok(block)
+    }
+
+    fn verify_to(&self, trusted: &LightBlock, height: u64) -> Result<LightBlock> {
+        let trusted_header = &trusted.signed_header.header;
+        let untrusted = self.fetch(height)?;
+        let header = &untrusted.signed_header.header;
+        if header.chain_id != trusted_header.chain_id {
+            return Err(anyhow!("chain id changed from {} to {} at height {}", trusted_header.chain_id, header.chain_id, height));
+        }
+        if header.time <= trusted_header.time {
+            return Err(anyhow!("header time at height {} is not after the trusted header", height));
+        }
+        if height == trusted_header.height.value() + 1 {
+            if header.validators_hash != trusted_header.next_validators_hash {
+                return Err(anyhow!("validator set at height {} does not match the trusted next validators", height));
+            }
+        } else if verify_commit(&untrusted, &trusted.next_validators, 1, 3).is_err() {
+            let pivot = (trusted_header.height.value() + height) / 2;
+            info!("bisecting to height {}", pivot);
+            let trusted = self.verify_to(trusted, pivot)?;
+            return self.verify_to(&trusted, height);
+        }
+        verify_commit(&untrusted, &untrusted.validators, 2, 3)?;
+        Ok(untrusted)
+    }
+
+    /// Verifies the header at `height` starting from `root` and returns its hash.
+    pub fn verify(&self, root: &TrustRoot, height: u64) -> Result<String> {
+        if height < root.height() {
+            return Err(anyhow!("trust height {} is below trust root height {}", height, root.height()));
+        }
+        let trusted = self.trusted_root(root)?;
+        info!("trusting header at height {} ({})", root.height(), trusted.signed_header.header.hash());
+        if height == root.height() {
+            return Ok(trusted.signed_header.header.hash().to_string());
+        }
+        let verified = self.verify_to(&trusted, height)?;
+        Ok(verified.signed_header.header.hash().to_string())
+    }
+}
+
+fn validate(block: &LightBlock, height: u64) -> Result<()> {
+    let header = &block.signed_header.header;
+    let commit = &block.signed_header.commit;
+    if header.height != Height::try_from(height)? || commit.height != header.height {
+        return Err(anyhow!("rpc returned the wrong header for height {}", height));
+    }
+    if commit.block_id.hash != header.hash() {
+        return Err(anyhow!("commit at height {} is for a different header", height));
+    }
+    if block.validators.hash() != header.validators_hash {
+        return Err(anyhow!("validator set at height {} does not match the header", height));
+    }
+    if block.next_validators.hash() != header.next_validators_hash {
+        return Err(anyhow!("next validator set at height {} does not match the header", height));
+    }
+    Ok(())
+}
+
+/// Requires validators in `validators` holding more than `numerator/denominator` of its voting power to have signed the commit.
+fn verify_commit(block: &LightBlock, validators: &Set, numerator: u64, denominator: u64) -> Result<()> {
+    let header = &block.signed_header.header;
+    let commit = &block.signed_header.commit;
+    let chain_id: chain::Id = header.chain_id.clone();
+    let mut seen = HashSet::new();
+    let mut signed_power = 0u64;
+    for (index, sig) in commit.signatures.iter().enumerate() {
+        let (validator_address, timestamp, signature) = match sig {
+            CommitSig::BlockIdFlagCommit { validator_address, timestamp, signature } => (validator_address, timestamp, signature),
+            _ => continue,
+        };
+        let validator = match validators.validator(*validator_address) {
+            Some(v) => v,
+            None => continue,
+        };
+        if !seen.insert(*validator_address) {
+            return Err(anyhow!("duplicate vote from {} at height {}", validator_address, header.height));
+        }
+        let vote = Vote {
+            vote_type: Type::Precommit,
+            height: commit.height,
+            round: commit.round,
+            block_id: Some(commit.block_id),
+            timestamp: Some(*timestamp),
+            validator_address: *validator_address,
+            validator_index: ValidatorIndex::try_from(u32::try_from(index)?)?,
+            signature: signature.clone(),
+        };
+        let signed_vote = SignedVote::from_vote(vote, chain_id.clone())
+            .ok_or(anyhow!("missing signature from {} at height {}", validator_address, header.height))?;
+        validator.verify_signature(&signed_vote.sign_bytes(), signed_vote.signature())
+            .map_err(|err| anyhow!("invalid signature from {} at height {}: {}", validator_address, header.height, err))?;
+        signed_power += validator.power();
+    }
+    let total_power = validators.total_voting_power().value();
+    if (signed_power as u128) * (denominator as u128) <= (total_power as u128) * (numerator as u128) {
+        return Err(anyhow!(
+            "insufficient voting power signed height {}: {} of {}", header.height, signed_power, total_power,
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use ed25519_dalek::{Keypair, PublicKey as DalekPublicKey, SecretKey, Signer};
+    use serde_json::{json, Value};
+    use tendermint::block::header::Version;
+    use tendermint::block::parts::Header as PartSetHeader;
+    use tendermint::block::{self, Commit, Header, Round};
+    use tendermint::vote::Power;
+    use tendermint::{account, AppHash, Hash, PublicKey, Signature};
+
+    use super::*;
+
+    const HEIGHTS: u64 = 30;
+
+    fn keypair(i: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[i + 1; 32]).unwrap();
+        let public = DalekPublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    fn public_key(key: &Keypair) -> PublicKey {
+        PublicKey::from_raw_ed25519(key.public.as_bytes()).unwrap()
+    }
+
+    /// Validators sign in groups of four that rotate by two every ten heights, so sets twenty heights apart share none.
+    fn validator_set(keys: &[Keypair], height: u64) -> Set {
+        let first = (((height - 1) / 10) * 2) as usize;
+        Set::without_proposer(keys[first..first + 4].iter().map(|k| Info::new(public_key(k), Power::from(10u32))).collect())
+    }
+
+    /// Recorded blocks of a chain whose validators all sign every height.
+    struct Chain {
+        headers: Vec<SignedHeader>,
+        validators: Vec<Set>,
+    }
+
+    impl Chain {
+        fn new() -> Self {
+            let keys: Vec<Keypair> = (0..10).map(keypair).collect();
+            let start = Time::now();
+            let mut headers = vec![];
+            let mut validators = vec![];
+            let mut last_block_id = None;
+            for height in 1..=HEIGHTS + 1 {
+                let set = validator_set(&keys, height);
+                let header = Header {
+                    version: Version { block: 11, app: 0 },
+                    chain_id: "mock-1".parse().unwrap(),
+                    height: Height::try_from(height).unwrap(),
+                    time: (start - Duration::from_secs(100 - height)).unwrap(),
+                    last_block_id,
+                    last_commit_hash: None,
+                    data_hash: None,
+                    validators_hash: set.hash(),
+                    next_validators_hash: validator_set(&keys, height + 1).hash(),
+                    consensus_hash: Hash::Sha256([1; 32]),
+                    app_hash: AppHash::try_from(vec![2; 32]).unwrap(),
+                    last_results_hash: None,
+                    evidence_hash: None,
+                    proposer_address: set.validators()[0].address,
+                };
+                let block_id = block::Id { hash: header.hash(), part_set_header: PartSetHeader::new(1, Hash::Sha256([3; 32])).unwrap() };
+                let timestamp = (header.time + Duration::from_secs(1)).unwrap();
+                let signatures = set.validators().iter().enumerate().map(|(index, v)| {
+                    let key = keys.iter().find(|k| account::Id::from(public_key(k)) == v.address).unwrap();
+                    let vote = Vote {
+                        vote_type: Type::Precommit,
+                        height: header.height,
+                        round: Round::default(),
+                        block_id: Some(block_id),
+                        timestamp: Some(timestamp),
+                        validator_address: v.address,
+                        validator_index: ValidatorIndex::try_from(index as u32).unwrap(),
+                        signature: None,
+                    };
+                    let placeholder = Signature::new(vec![0; 64]).unwrap().unwrap();
+                    let sign_bytes = SignedVote::new(vote, header.chain_id.clone(), v.address, placeholder).sign_bytes();
+                    let signature = Signature::new(key.sign(&sign_bytes).to_bytes()).unwrap();
+                    CommitSig::BlockIdFlagCommit { validator_address: v.address, timestamp, signature }
+                }).collect();
+                let commit = Commit { height: header.height, round: Round::default(), block_id, signatures };
+                headers.push(SignedHeader::new(header, commit).unwrap());
+                validators.push(set);
+                last_block_id = Some(block_id);
+            }
+            Self { headers, validators }
+        }
+
+        fn header(&mut self, height: u64) -> &mut SignedHeader {
+            &mut self.headers[height as usize - 1]
+        }
+
+        fn hash(&self, height: u64) -> String {
+            self.headers[height as usize - 1].header.hash().to_string()
+        }
+
+        fn root(&self, height: u64) -> TrustRoot {
+            format!("{}:{}", height, self.hash(height)).parse().unwrap()
+        }
+    }
+
+    /// JSON-RPC server answering `commit` and `validators` from recorded blocks, logging the commit heights asked for.
+    struct MockRpc {
+        url: String,
+        commits: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl MockRpc {
+        fn serve(chain: Chain) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let commits = Arc::new(Mutex::new(vec![]));
+            let log = commits.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(&stream);
+                    let mut content_length = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("content-length") {
+                                content_length = value.trim().parse().unwrap();
+                            }
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).unwrap();
+                    let request: Value = serde_json::from_slice(&body).unwrap();
+                    let height: u64 = request["params"]["height"].as_str().unwrap().parse().unwrap();
+                    let index = height as usize - 1;
+                    let result = match request["method"].as_str().unwrap() {
+                        "commit" => {
+                            log.lock().unwrap().push(height);
+                            json!({ "signed_header": chain.headers[index], "canonical": true })
+                        },
+                        "validators" => {
+                            let validators = chain.validators[index].validators();
+                            json!({
+                                "block_height": height.to_string(),
+                                "validators": validators,
+                                "count": validators.len().to_string(),
+                                "total": validators.len().to_string(),
+                            })
+                        },
+                        method => panic!("unexpected rpc method {}", method),
+                    };
+                    let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }).to_string();
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        response.len(), response,
+                    ).unwrap();
+                }
+            });
+            Self { url, commits }
+        }
+
+        fn verify(&self, root: &TrustRoot, height: u64) -> Result<String> {
+            let rpc = BlockingRpc::from_url(&self.url)?;
+            LightClient::new(&rpc, Duration::from_secs(3600)).verify(root, height)
+        }
+
+        fn commits(&self) -> Vec<u64> {
+            self.commits.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn verifies_adjacent_header() {
+        let chain = Chain::new();
+        let (root, expected) = (chain.root(10), chain.hash(11));
+        let rpc = MockRpc::serve(chain);
+        assert_eq!(rpc.verify(&root, 11).unwrap(), expected);
+        assert_eq!(rpc.commits(), vec![10, 11]);
+    }
+
+    #[test]
+    fn skips_to_header_signed_by_trusted_validators() {
+        let chain = Chain::new();
+        let (root, expected) = (chain.root(1), chain.hash(10));
+        let rpc = MockRpc::serve(chain);
+        assert_eq!(rpc.verify(&root, 10).unwrap(), expected);
+        assert_eq!(rpc.commits(), vec![1, 10]);
+    }
+
+    #[test]
+    fn bisects_across_validator_set_changes() {
+        let chain = Chain::new();
+        let (root, expected) = (chain.root(1), chain.hash(HEIGHTS));
+        let rpc = MockRpc::serve(chain);
+        assert_eq!(rpc.verify(&root, HEIGHTS).unwrap(), expected);
+        assert!(rpc.commits().len() > 2);
+    }
+
+    #[test]
+    fn rejects_wrong_root_hash() {
+        let chain = Chain::new();
+        let root = format!("1:{}", chain.hash(2)).parse().unwrap();
+        let rpc = MockRpc::serve(chain);
+        let err = rpc.verify(&root, 10).unwrap_err().to_string();
+        assert!(err.contains("header hash at trust root height 1"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_bad_signature() {
+        let mut chain = Chain::new();
+        let forged = match &chain.header(9).commit.signatures[0] {
+            CommitSig::BlockIdFlagCommit { signature, .. } => signature.clone(),
+            _ => unreachable!(),
+        };
+        if let CommitSig::BlockIdFlagCommit { signature, .. } = &mut chain.header(10).commit.signatures[0] {
+            *signature = forged;
+        }
+        let root = chain.root(9);
+        let rpc = MockRpc::serve(chain);
+        let err = rpc.verify(&root, 10).unwrap_err().to_string();
+        assert!(err.contains("invalid signature") && err.contains("height 10"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_validator_set_mismatch() {
+        let mut chain = Chain::new();
+        chain.validators[9] = chain.validators[20].clone();
+        let root = chain.root(1);
+        let rpc = MockRpc::serve(chain);
+        let err = rpc.verify(&root, 10).unwrap_err().to_string();
+        assert!(err.contains("validator set at height 10 does not match the header"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_genesis_without_validators() {
+        let err = TrustRoot::from_genesis(r#"{"chain_id": "mock-1", "validators": []}"#.as_bytes()).err().unwrap().to_string();
+        assert!(err.contains("--statesync-trust-root"), "{}", err);
+    }
+
+    fn genesis(chain: &Chain, initial_height: Value) -> String {
+        json!({
+            "genesis_time": "2023-01-01T00:00:00Z",
+            "chain_id": "mock-1",
+            "initial_height": initial_height,
+            "validators": chain.validators[0].validators(),
+            "app_state": { "bank": { "balances": vec![json!({ "address": "mock1", "coins": [] }); 1000] } },
+        }).to_string()
+    }
+
+    #[test]
+    fn verifies_from_genesis_with_string_or_number_initial_height() {
+        for initial_height in [json!("1"), json!(1)] {
+            let chain = Chain::new();
+            let root = TrustRoot::from_genesis(genesis(&chain, initial_height).as_bytes()).unwrap();
+            let expected = chain.hash(10);
+            assert_eq!(MockRpc::serve(chain).verify(&root, 10).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_genesis_initial_height() {
+        let chain = Chain::new();
+        for initial_height in [json!("-1"), json!("one"), json!(1.5)] {
+            let err = TrustRoot::from_genesis(genesis(&chain, initial_height.clone()).as_bytes()).err().unwrap().to_string();
+            assert_eq!(err, format!("invalid genesis initial_height {}", initial_height));
+        }
+    }
+}
@@ -1,271 +1,829 @@
-mod config;
-mod rpc;
-
-use std::path::PathBuf;
-use std::fs::{read_to_string, File};
-use std::io::Write;
+use std::cell::OnceCell;
+use std::path::{Path, PathBuf};
+use std::fs::{metadata, read_to_string, remove_file, rename, File};
+use std::io::{self, IsTerminal, Read};
 use std::env;
+use std::process;
+use std::time::Duration;
 
 use anyhow::{Result, anyhow};
-use clap::{Parser, Subcommand};
-use serde_json::{from_value, from_str, to_value, to_string, to_string_pretty, Value};
-use json_patch::merge;
-use log::{info, error};
+use clap::{Args, Parser, Subcommand};
+use serde_json::{from_value, to_value};
+use log::{info, log_enabled, warn, error, Level};
 use gethostname::gethostname;
+use similar::TextDiff;
+
+use starsign::archive::open_genesis;
+use starsign::cache::{cache_dir, CacheDownload, GenesisCache};
+use starsign::backup::{backup_file, next_stamp, rollback, temp_path, write_atomic};
+use starsign::download::DownloadOptions;
+use starsign::config::{default_wasmd_config, CosmosChainConfig, SdkVersion, TendermintVersion};
+use starsign::detect::detect_versions;
+use starsign::genesis::{sha256_file, validate_genesis, verify_sha256_hex, write_genesis};
+use starsign::peers::{join_peers, parse_peers, peers_from_rpc, Peer};
+use starsign::probe::{fastest_peers, probe_peers, ProbeOptions};
+use starsign::profile::{chain_profile, profile_dirs};
+use starsign::registry::find_registry_chain;
+use starsign::role::Role;
+use starsign::light::TrustRoot;
+use starsign::overrides::Overrides;
+use starsign::statesync::{light_verify_trust_point, safe_trust_period, verified_trust_point};
+use starsign::update::update_toml;
 
-use crate::config::{default_config, default_wasmd_config};
-use crate::rpc::BlockingRpc;
+/// Files larger than this are compared by hash instead of diffed in dry-run mode.
+const MAX_DIFF_SIZE: u64 = 16 << 20;
 
 #[derive(Parser)]
 #[command(name = "starsign", author = "mintthemoon <mint@mintthemoon.xyz>", version = "0.1.4")]
 #[command(about = "Configure your node environment")]
 struct Cli {
+    /// only log warnings and errors, without progress bars
+    #[arg(short, long, global = true)]
+    quiet: bool,
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
+#[derive(Args)]
+struct ChainArgs {
+    /// chain id
+    #[arg(short, long)]
+    chain: Option<String>,
+    /// chain-registry directory or chain.json file
+    #[arg(long)]
+    chain_registry: Option<PathBuf>,
+    /// chain profile directory (searched before ~/.config/starsign/chains)
+    #[arg(long)]
+    profile_dir: Option<PathBuf>,
+    /// cosmos sdk version whose app.toml layout to write (default from the chain profile)
+    #[arg(long)]
+    sdk_version: Option<SdkVersion>,
+    /// node role preset applied over the chain defaults
+    #[arg(long)]
+    role: Option<Role>,
+}
+
+#[derive(Args)]
+struct DetectArgs {
+    /// detect sdk and tendermint versions from an rpc node (default the chain's statesync rpc servers)
+    #[arg(long, value_name = "RPC", num_args = 0..=1)]
+    detect_versions: Option<Option<String>>,
+}
+
+impl DetectArgs {
+    fn apply(&self, cfg: &mut CosmosChainConfig) -> Result<()> {
+        let rpc = match &self.detect_versions {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let servers = match rpc {
+            Some(r) => vec![r.clone()],
+            None => cfg.tendermint.statesync.rpc_servers.clone(),
+        };
+        let versions = detect_versions(&servers)?;
+        if let Some(v) = versions.sdk {
+            cfg.sdk_version = v;
+        }
+        if let Some(v) = versions.tendermint {
+            cfg.tendermint_version = v;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// output directory
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// print a diff against existing files instead of writing them
+    #[arg(long, visible_alias = "diff")]
+    dry_run: bool,
+    /// number of backups to keep per file (0 disables backups)
+    #[arg(long, default_value_t = 5)]
+    backups: usize,
+    #[arg(skip)]
+    stamp: OnceCell<u64>,
+}
+
+impl OutputArgs {
+    /// Stamp shared by every backup this run makes, picked on first use so it is newer than the existing ones.
+    fn stamp(&self) -> Result<u64> {
+        if let Some(s) = self.stamp.get() {
+            return Ok(*s);
+        }
+        let stamp = next_stamp(self.output.as_deref().unwrap_or(Path::new(".")))?;
+        Ok(*self.stamp.get_or_init(|| stamp))
+    }
+}
+
+#[derive(Args)]
+struct TendermintArgs {
+    /// node moniker
+    #[arg(short, long)]
+    moniker: Option<String>,
+    /// enable statesync
+    #[arg(short, long)]
+    statesync: bool,
+    /// custom statesync rpc (repeatable)
+    #[arg(long)]
+    statesync_rpc: Vec<String>,
+    /// number of statesync rpc servers that must agree on the trust hash (default majority)
+    #[arg(long)]
+    statesync_quorum: Option<usize>,
+    /// statesync trust period (default 2/3 of the chain's unbonding time)
+    #[arg(long)]
+    statesync_trust_period: Option<String>,
+    /// verify the statesync trust hash with a light client, rooted at the validators listed in the output genesis.json
+    #[arg(long)]
+    statesync_verify: bool,
+    /// light client root of trust as <height>:<hash> (implies --statesync-verify, required when genesis lists no validators)
+    #[arg(long)]
+    statesync_trust_root: Option<String>,
+    /// custom statesync snapshot interval (default 2000)
+    #[arg(long)]
+    statesync_interval: Option<u64>,
+    /// tendermint or cometbft version whose config.toml layout to write (default from the chain profile)
+    #[arg(long)]
+    tendermint_version: Option<TendermintVersion>,
+    #[command(flatten)]
+    detect: DetectArgs,
+}
+
+#[derive(Args)]
+struct PeerArgs {
+    /// discover persistent peers from an rpc node's connected peers
+    #[arg(long)]
+    peers_from_rpc: Option<String>,
+    /// number of discovered or probed peers to keep
+    #[arg(long, default_value_t = 10)]
+    peer_count: usize,
+    /// dial persistent peers and seeds, keeping only the fastest reachable ones
+    #[arg(long)]
+    probe_peers: bool,
+    #[command(flatten)]
+    probe: ProbeArgs,
+    /// validators behind this sentry as id@host:port (comma separated), kept private and always connected
+    #[arg(long)]
+    private_peers: Option<String>,
+}
+
+#[derive(Args)]
+struct ProbeArgs {
+    /// peer dial timeout in milliseconds
+    #[arg(long, default_value_t = 3000)]
+    probe_timeout: u64,
+    /// number of peers to dial at once
+    #[arg(long, default_value_t = 16)]
+    probe_concurrency: usize,
+    /// perform the p2p handshake and require the node id to match
+    #[arg(long)]
+    verify_node_id: bool,
+}
+
+impl ProbeArgs {
+    fn options(&self) -> ProbeOptions {
+        ProbeOptions {
+            timeout: Duration::from_millis(self.probe_timeout),
+            concurrency: self.probe_concurrency,
+            verify_node_id: self.verify_node_id,
+        }
+    }
+}
+
+#[derive(Args)]
+struct OverrideArgs {
+    /// parameter overrides as a json merge patch, or @file (json, yaml or toml) or @- for stdin (repeatable)
+    #[arg(long)]
+    custom: Vec<String>,
+    /// json patch operations (rfc 6902), applied after --custom (repeatable, applied in order)
+    #[arg(long)]
+    patch: Vec<String>,
+    /// set a value by dot path as <path>=<value>, applied last (repeatable)
+    #[arg(long)]
+    set: Vec<String>,
+    /// warn about override keys starsign does not know instead of failing
+    #[arg(long)]
+    lenient: bool,
+}
+
+impl OverrideArgs {
+    fn overrides(&self) -> Result<Overrides> {
+        Ok(Overrides { lenient: self.lenient, ..Overrides::parse(&self.custom, &self.patch, &self.set)? })
+    }
+}
+
+#[derive(Args)]
+struct GenesisArgs {
+    /// custom genesis url
+    #[arg(short, long)]
+    genesis_url: Option<String>,
+    /// use existing genesis file
+    #[arg(long)]
+    genesis_file: Option<PathBuf>,
+    /// expected sha256 of the genesis json (overrides the chain's pinned hash)
+    #[arg(long)]
+    genesis_sha256: Option<String>,
+    /// genesis cache directory (default ~/.cache/starsign)
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+    /// always download genesis, bypassing the cache
+    #[arg(long, conflicts_with = "offline")]
+    no_cache: bool,
+    /// only use cached or local genesis, never download
+    #[arg(long)]
+    offline: bool,
+    /// download connect timeout in seconds
+    #[arg(long, default_value_t = 10)]
+    connect_timeout: u64,
+    /// download read timeout in seconds
+    #[arg(long, default_value_t = 60)]
+    read_timeout: u64,
+    /// download retries, with exponential backoff
+    #[arg(long, default_value_t = 5)]
+    retries: u32,
+    /// write genesis even if it fails validation
+    #[arg(long)]
+    force: bool,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// configure app.toml
     ConfigApp {
-        /// chain id
-        #[arg(short, long)]
-        chain: Option<String>,
-        /// output directory
-        #[arg(short, long)]
-        output: Option<PathBuf>,
-        /// parameter overrides json
+        #[command(flatten)]
+        chain: ChainArgs,
+        #[command(flatten)]
+        detect: DetectArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        overrides: OverrideArgs,
+        /// update existing file in place, keeping comments and unknown keys
         #[arg(long)]
-        custom: Option<String>,
+        update: bool,
     },
     /// configure config.toml
     ConfigTendermint {
-        /// chain id
-        #[arg(short, long)]
-        chain: Option<String>,
-        /// output directory
-        #[arg(short, long)]
-        output: Option<PathBuf>,
-        /// parameter overrides json
+        #[command(flatten)]
+        chain: ChainArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        overrides: OverrideArgs,
+        /// update existing file in place, keeping comments and unknown keys
         #[arg(long)]
-        custom: Option<String>,
-        /// node moniker
-        #[arg(short, long)]
-        moniker: Option<String>,
-        /// enable statesync
-        #[arg(short, long)]
-        statesync: bool,
-        /// custom statesync rpc
-        #[arg(long)]
-        statesync_rpc: Option<String>,
-        /// custom statesync snapshot interval (default 2000)
+        update: bool,
+        #[command(flatten)]
+        tendermint: TendermintArgs,
+        #[command(flatten)]
+        peers: PeerArgs,
+    },
+    /// configure client.toml
+    ConfigClient {
+        #[command(flatten)]
+        chain: ChainArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        overrides: OverrideArgs,
+        /// update existing file in place, keeping comments and unknown keys
         #[arg(long)]
-        statesync_interval: Option<u64>,
+        update: bool,
     },
     /// configure genesis.json
     ConfigGenesis {
-        /// chain id
-        #[arg(short, long)]
-        chain: Option<String>,
-        /// output directory
-        #[arg(short, long)]
-        output: Option<PathBuf>,
-        /// parameter overrides json
-        #[arg(long)]
-        custom: Option<String>,
-        /// custom genesis url
-        #[arg(short, long)]
-        genesis_url: Option<String>,
-        /// use existing genesis file
-        #[arg(long)]
-        genesis_file: Option<PathBuf>,
+        #[command(flatten)]
+        chain: ChainArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        overrides: OverrideArgs,
+        #[command(flatten)]
+        genesis: GenesisArgs,
     },
     /// configure all supported chain files
     Config {
-        /// chain id
-        #[arg(short, long)]
-        chain: Option<String>,
-        /// output directory
-        #[arg(short, long)]
-        output: Option<PathBuf>,
-        /// parameter overrides json
+        #[command(flatten)]
+        chain: ChainArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        overrides: OverrideArgs,
+        /// update existing file in place, keeping comments and unknown keys
         #[arg(long)]
-        custom: Option<String>,
-        /// node moniker
-        #[arg(short, long)]
-        moniker: Option<String>,
-        /// custom genesis url
-        #[arg(short, long)]
-        genesis_url: Option<String>,
-        /// use existing genesis file
+        update: bool,
+        #[command(flatten)]
+        tendermint: TendermintArgs,
+        #[command(flatten)]
+        peers: PeerArgs,
+        #[command(flatten)]
+        genesis: GenesisArgs,
+    },
+    /// list usable peers connected to an rpc node
+    Peers {
+        /// rpc url
         #[arg(long)]
-        genesis_file: Option<PathBuf>,
-        /// enable statesync
-        #[arg(short, long)]
-        statesync: bool,
-        /// custom statesync rpc
+        rpc: String,
+        /// number of peers to list
+        #[arg(long, default_value_t = 10)]
+        peer_count: usize,
+    },
+    /// dial peers and print them ranked by latency
+    ProbePeers {
+        #[command(flatten)]
+        chain: ChainArgs,
+        /// also probe peers connected to an rpc node
         #[arg(long)]
-        statesync_rpc: Option<String>,
-        /// custom statesync snapshot interval (default 2000)
+        rpc: Option<String>,
+        /// comma-separated peers to probe instead of the chain's seeds and persistent peers
         #[arg(long)]
-        statesync_interval: Option<u64>,
+        peers: Option<String>,
+        #[command(flatten)]
+        probe: ProbeArgs,
+    },
+    /// restore the most recent backup set
+    Rollback {
+        /// output directory
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// number of backups to keep per file
+        #[arg(long, default_value_t = 5)]
+        backups: usize,
     },
 }
 
-fn write_file(path: &PathBuf, content: &str) -> Result<()> {
-    File::create(path)?.write_all(&content.as_bytes())?;
+fn write_file(output: &OutputArgs, path: &Path, content: &str) -> Result<()> {
+    if path.exists() && read_to_string(path)? == content {
+        info!("{} is up to date", path.to_string_lossy());
+        return Ok(());
+    }
+    if let Some(backup) = backup_file(path, output.stamp()?, output.backups)? {
+        info!("backed up {} to {}", path.to_string_lossy(), backup.to_string_lossy());
+    }
+    write_atomic(path, content.as_bytes())?;
     info!("wrote {}", path.to_string_lossy());
     Ok(())
 }
 
-fn config_app(chain: &Option<String>, output: &Option<PathBuf>, custom: &Option<String>) -> Result<()> {
-    let default_cfg = match chain {
-        Some(c) => default_config(c).ok_or(anyhow!("chain not supported: {}", c))?,
-        None => default_wasmd_config(),
+fn output_path(output: &OutputArgs, name: &str) -> PathBuf {
+    match &output.output {
+        Some(o) => o.join(name),
+        None => PathBuf::new().join(name),
+    }
+}
+
+fn output_file(output: &OutputArgs, path: &Path, content: &str) -> Result<bool> {
+    if !output.dry_run {
+        write_file(output, path, content)?;
+        return Ok(false);
+    }
+    print_diff(path, content)
+}
+
+fn print_diff(path: &Path, content: &str) -> Result<bool> {
+    let exists = path.exists();
+    let current = if exists { read_to_string(path)? } else { String::new() };
+    if current == content {
+        info!("{} is up to date", path.to_string_lossy());
+        return Ok(false);
+    }
+    let name = path.to_string_lossy();
+    let old_name = if exists { name.as_ref() } else { "/dev/null" };
+    print!("{}", TextDiff::from_lines(current.as_str(), content).unified_diff().header(old_name, &name));
+    Ok(true)
+}
+
+/// Moves a fully written temporary file into place, or diffs it against the existing file in dry-run mode.
+fn output_staged(output: &OutputArgs, staged: &Path, path: &Path) -> Result<bool> {
+    if !output.dry_run {
+        if path.exists() && sha256_file(path)? == sha256_file(staged)? {
+            remove_file(staged)?;
+            info!("{} is up to date", path.to_string_lossy());
+            return Ok(false);
+        }
+        if let Some(backup) = backup_file(path, output.stamp()?, output.backups)? {
+            info!("backed up {} to {}", path.to_string_lossy(), backup.to_string_lossy());
+        }
+        rename(staged, path)?;
+        info!("wrote {}", path.to_string_lossy());
+        return Ok(false);
+    }
+    let small = |p: &Path| metadata(p).map(|m| m.len() <= MAX_DIFF_SIZE).unwrap_or(true);
+    let changed = if small(staged) && small(path) {
+        print_diff(path, &read_to_string(staged)?)
+    } else {
+        let new_hash = sha256_file(staged)?;
+        let old_hash = if path.exists() { sha256_file(path)? } else { String::new() };
+        if old_hash == new_hash {
+            info!("{} is up to date", path.to_string_lossy());
+        } else {
+            println!("{} differs (sha256 {} -> {})", path.to_string_lossy(), old_hash, new_hash);
+        }
+        Ok(old_hash != new_hash)
     };
-    let cfg = match custom {
-        Some(c) => {
-            let patch = from_str(&format!("{{\"app\": {}}}", c))?;
-            let mut base = to_value(default_cfg)?;
-            merge(&mut base, &patch);
-            info!("customized app config");
-            from_value(base)?
-        },
-        None => default_cfg,
+    remove_file(staged)?;
+    changed
+}
+
+/// Chain defaults and override paths deciding which keys `--update` changes in an existing file.
+struct Update {
+    defaults: CosmosChainConfig,
+    overrides: Vec<Vec<String>>,
+}
+
+fn render_toml(
+    path: &Path, cfg: &CosmosChainConfig, update: Option<&Update>, render: fn(&CosmosChainConfig) -> Result<String>,
+) -> Result<String> {
+    let rendered = render(cfg)?;
+    let update = match update {
+        Some(u) => u,
+        None => return Ok(rendered),
     };
-    let path = match output {
-        Some(o) => o.join("app.toml"),
-        None => PathBuf::new().join("app.toml"),
+    if !path.exists() {
+        info!("{} not found, writing full config", path.to_string_lossy());
+        return Ok(rendered);
+    }
+    let updated = update_toml(&read_to_string(path)?, &rendered, &render(&update.defaults)?, &update.overrides)?;
+    info!("updated existing {}", path.to_string_lossy());
+    Ok(updated)
+}
+
+/// Resolves the chain config and the chain id it belongs to, detecting versions for commands that render them.
+fn chain_config(chain: &ChainArgs, detect: Option<&DetectArgs>) -> Result<(String, CosmosChainConfig)> {
+    let registry = match &chain.chain_registry {
+        Some(r) => find_registry_chain(r, chain.chain.as_deref())?,
+        None => None,
     };
-    write_file(&path, &cfg.get_app_config()?)?;
-    Ok(())
+    let chain_id = chain.chain.as_ref().or(registry.as_ref().map(|r| &r.chain_id));
+    let dirs = profile_dirs(&chain.profile_dir);
+    let mut cfg = match (chain_id, &registry) {
+        (Some(c), None) => chain_profile(&dirs, c)?.ok_or(anyhow!("chain not supported: {}", c))?,
+        (Some(c), Some(_)) => chain_profile(&dirs, c)?.unwrap_or_else(default_wasmd_config),
+        (None, _) => default_wasmd_config(),
+    };
+    let chain_id = chain_id.cloned().unwrap_or_default();
+    if let Some(r) = registry {
+        r.apply(&mut cfg);
+        info!("applied chain registry profile for {}", r.chain_id);
+    }
+    if let Some(d) = detect {
+        d.apply(&mut cfg)?;
+    }
+    if let Some(v) = chain.sdk_version {
+        cfg.sdk_version = v;
+    }
+    Ok((chain_id, cfg))
 }
 
-fn config_tendermint(
-    chain: &Option<String>, output: &Option<PathBuf>, custom: &Option<String>, moniker: &Option<String>, statesync: &bool, statesync_rpc: &Option<String>, statesync_interval: &Option<u64>,
-) -> Result<()> {
-    let default_cfg = match chain {
-        Some(c) => default_config(c).ok_or(anyhow!("chain not supported: {}", c))?,
-        None => default_wasmd_config(),
+/// Overrides for one section, preceded by the role preset.
+fn with_role(role: Option<Role>, section: &str, overrides: Overrides) -> Overrides {
+    let (role, preset) = match role.and_then(|r| Some((r, r.preset().get(section)?.clone()))) {
+        Some(p) => p,
+        None => return overrides,
     };
-    let mut cfg = match custom {
-        Some(c) => {
-            let patch = from_str(&format!("{{\"tendermint\": {}}}", c))?;
-            let mut base = to_value(default_cfg)?;
-            merge(&mut base, &patch);
-            info!("customized tendermint config");
-            from_value(base)?
+    info!("applied {} role preset to {}", role, section);
+    let mut merges = vec![preset];
+    merges.extend(overrides.merges);
+    Overrides { merges, ..overrides }
+}
+
+/// Applies overrides to one section of the chain config.
+fn customize(cfg: CosmosChainConfig, section: &str, overrides: &Overrides) -> Result<CosmosChainConfig> {
+    if overrides.is_empty() {
+        return Ok(cfg);
+    }
+    let mut base = to_value(cfg)?;
+    overrides.apply(&mut base[section])?;
+    let cfg: CosmosChainConfig = from_value(base.clone())?;
+    overrides.check_keys(&base[section], &to_value(&cfg)?[section], section)?;
+    info!("customized {} config", section);
+    Ok(cfg)
+}
+
+fn config_app(cfg: &CosmosChainConfig, output: &OutputArgs, update: Option<&Update>) -> Result<bool> {
+    let path = output_path(output, "app.toml");
+    output_file(output, &path, &render_toml(&path, cfg, update, CosmosChainConfig::get_app_config)?)
+}
+
+fn config_client(cfg: &CosmosChainConfig, output: &OutputArgs, update: Option<&Update>) -> Result<bool> {
+    let path = output_path(output, "client.toml");
+    output_file(output, &path, &render_toml(&path, cfg, update, CosmosChainConfig::get_client_config)?)
+}
+
+fn config_tendermint(
+    mut cfg: CosmosChainConfig, role: Option<Role>, output: &OutputArgs, update: Option<Update>,
+    tendermint: &TendermintArgs, peers: &PeerArgs,
+) -> Result<bool> {
+    // keys set by flags, which --update always applies
+    let mut flagged = vec![];
+    let hostname = gethostname().into_string().unwrap_or("node".to_string());
+    match &tendermint.moniker {
+        Some(m) => {
+            cfg.tendermint.moniker = m.clone();
+            flagged.push("moniker");
         },
-        None => default_cfg,
-    };
-    match moniker {
-        Some(m) => { cfg.tendermint.moniker = m.clone(); },
-        None => { cfg.tendermint.moniker = gethostname().into_string().unwrap_or("node".to_string()); }
+        None => { cfg.tendermint.moniker = hostname.clone(); }
     }
     info!("using moniker {}", cfg.tendermint.moniker);
-    if *statesync {
-        let rpc_url = match statesync_rpc {
-            Some(r) => {
-                cfg.tendermint.statesync.rpc_servers = vec![r.clone(), r.clone()];
-                r
-            },
-            None => {
-                if cfg.tendermint.statesync.rpc_servers.is_empty() {
-                    return Err(anyhow!("statesync enabled but no rpc servers are configured"));
-                }
-                if cfg.tendermint.statesync.rpc_servers.len() == 1 {
-                    cfg.tendermint.statesync.rpc_servers.push(cfg.tendermint.statesync.rpc_servers[0].clone())
-                }
-                &cfg.tendermint.statesync.rpc_servers[0]
+    if let Some(v) = tendermint.tendermint_version {
+        cfg.tendermint_version = v;
+    }
+    if let Some(rpc) = &peers.peers_from_rpc {
+        let discovered = peers_from_rpc(rpc)?;
+        let persistent_peers = &discovered[..discovered.len().min(peers.peer_count)];
+        cfg.tendermint.p2p.persistent_peers = join_peers(persistent_peers);
+        flagged.push("p2p.persistent_peers");
+        info!("using {} persistent peers from {}", persistent_peers.len(), rpc);
+    }
+    if peers.probe_peers {
+        let p2p = &mut cfg.tendermint.p2p;
+        let options = peers.probe.options();
+        p2p.persistent_peers = join_peers(&fastest_peers(&parse_peers(&p2p.persistent_peers), peers.peer_count, &options));
+        p2p.seeds = join_peers(&fastest_peers(&parse_peers(&p2p.seeds), peers.peer_count, &options));
+        flagged.extend(["p2p.persistent_peers", "p2p.seeds"]);
+    }
+    if let Some(private) = &peers.private_peers {
+        let private = parse_peers(private);
+        let p2p = &mut cfg.tendermint.p2p;
+        let mut persistent = parse_peers(&p2p.persistent_peers);
+        persistent.retain(|p| !private.iter().any(|v| v.id == p.id));
+        persistent.extend(private.iter().cloned());
+        let ids = private.iter().map(|p| p.id.clone()).collect::<Vec<String>>().join(",");
+        p2p.persistent_peers = join_peers(&persistent);
+        p2p.private_peer_ids = ids.clone();
+        p2p.unconditional_peer_ids = ids;
+        flagged.extend(["p2p.persistent_peers", "p2p.private_peer_ids", "p2p.unconditional_peer_ids"]);
+        info!("using {} private peers", private.len());
+    }
+    if role == Some(Role::Sentry) && cfg.tendermint.p2p.private_peer_ids.is_empty() {
+        warn!("sentry has no private peers, pass its validators with --private-peers");
+    }
+    if tendermint.statesync {
+        if !tendermint.statesync_rpc.is_empty() {
+            cfg.tendermint.statesync.rpc_servers = tendermint.statesync_rpc.clone();
+        }
+        let statesync = &mut cfg.tendermint.statesync;
+        let trust = verified_trust_point(
+            &statesync.rpc_servers, tendermint.statesync_interval.unwrap_or(2000), tendermint.statesync_quorum,
+        )?;
+        let configured_trust_period = tendermint.statesync_trust_period.clone()
+            .or(statesync.trust_period.clone());
+        if let Some(p) = &configured_trust_period {
+            statesync.trust_period = Some(p.clone());
+            flagged.push("statesync.trust_period");
+        }
+        if let Some(server) = trust.servers.first() {
+            if let Some(p) = safe_trust_period(server, configured_trust_period.as_deref())? {
+                info!("using statesync trust period {}", p);
+                statesync.trust_period = Some(p);
+                flagged.push("statesync.trust_period");
             }
-        };
-        let rpc = BlockingRpc::from_url(rpc_url)?;
-        let sync_info = rpc.status()?.sync_info;
-        let latest_height = sync_info.latest_block_height.value();
-        let snapshot_interval = statesync_interval.unwrap_or(2000);
-        let snapshot_height = (latest_height / snapshot_interval) * snapshot_interval;
-        let trust_hash = rpc.block(snapshot_height.try_into()?)?.block_id.hash.to_string();
-        cfg.tendermint.statesync.enable = true;
-        cfg.tendermint.statesync.trust_height = snapshot_height;
-        cfg.tendermint.statesync.trust_hash = trust_hash.clone();
-        info!("enabled statesync to height {} ({}) from {}", snapshot_height, trust_hash, rpc_url);
-    }
-    let path = match output {
-        Some(o) => o.join("config.toml"),
-        None => PathBuf::new().join("config.toml"),
+        }
+        if tendermint.statesync_verify || tendermint.statesync_trust_root.is_some() {
+            let root = match &tendermint.statesync_trust_root {
+                Some(r) => r.parse()?,
+                None => {
+                    let genesis_path = output_path(output, "genesis.json");
+                    let genesis = File::open(&genesis_path).map_err(|err| anyhow!(
+                        "cannot read {} for light client root of trust, use --statesync-trust-root: {}",
+                        genesis_path.to_string_lossy(), err,
+                    ))?;
+                    TrustRoot::from_genesis(genesis)?
+                },
+            };
+            light_verify_trust_point(&trust, &root, statesync.trust_period_or_default())?;
+        }
+        if statesync.rpc_servers.len() == 1 {
+            statesync.rpc_servers.push(statesync.rpc_servers[0].clone());
+        }
+        statesync.enable = true;
+        statesync.trust_height = trust.height;
+        statesync.trust_hash = trust.hash.clone();
+        flagged.extend(["statesync.enable", "statesync.rpc_servers", "statesync.trust_height", "statesync.trust_hash"]);
+        info!("enabled statesync to height {} ({})", trust.height, trust.hash);
+    }
+    let update = update.map(|mut u| {
+        u.defaults.tendermint_version = cfg.tendermint_version;
+        u.defaults.tendermint.moniker = hostname;
+        u.overrides.extend(flagged.iter().map(|f| f.split('.').map(str::to_string).collect()));
+        u
+    });
+    let path = output_path(output, "config.toml");
+    output_file(output, &path, &render_toml(&path, &cfg, update.as_ref(), CosmosChainConfig::get_tendermint_config)?)
+}
+
+/// Opens genesis from a file, the cache or the network, returning where a fresh download is being cached.
+fn genesis_source(cfg: &CosmosChainConfig, genesis: &GenesisArgs) -> Result<(Box<dyn Read>, Option<CacheDownload>)> {
+    if let Some(f) = &genesis.genesis_file {
+        return Ok((open_genesis(File::open(f)?, None)?, None));
+    }
+    let dir = cache_dir(&genesis.cache_dir).filter(|_| !genesis.no_cache && !cfg.genesis_url.starts_with("file://"));
+    let cache = dir.as_deref().map(GenesisCache::new);
+    if let Some(cached) = cache.as_ref().and_then(|c| c.lookup(&cfg.genesis_url, &cfg.genesis_sha256)) {
+        info!("using cached genesis {}", cached.to_string_lossy());
+        return Ok((Box::new(File::open(cached)?), None));
+    }
+    if genesis.offline && !cfg.genesis_url.starts_with("file://") {
+        return Err(anyhow!("genesis for {} is not cached and --offline is set", cfg.genesis_url));
+    }
+    let options = DownloadOptions {
+        connect_timeout: Duration::from_secs(genesis.connect_timeout),
+        read_timeout: Duration::from_secs(genesis.read_timeout),
+        retries: genesis.retries,
+        progress: log_enabled!(Level::Info) && io::stderr().is_terminal(),
+        partial_dir: dir.map(|d| d.join("partial")).unwrap_or_else(|| env::temp_dir().join("starsign")),
     };
-    write_file(&path, &cfg.get_tendermint_config()?)?;
-    Ok(())
+    let source = cfg.genesis_reader(&options)?;
+    match cache {
+        Some(c) => {
+            let (tee, download) = c.download(source)?;
+            Ok((Box::new(tee), Some(download)))
+        },
+        None => Ok((source, None)),
+    }
 }
 
 fn config_genesis(
-    chain: &Option<String>, output: &Option<PathBuf>, custom: &Option<String>, genesis_url: &Option<String>, genesis_file: &Option<PathBuf>,
-) -> Result<()> {
-    let mut cfg = match chain {
-        Some(c) => default_config(c).ok_or(anyhow!("chain not supported: {}", c))?,
-        None => default_wasmd_config(),
-    };
-    genesis_url.as_ref().map(|url| cfg.genesis_url = url.clone());
-    let default_genesis = match genesis_file {
-        Some(f) => read_to_string(f)?,
-        None => cfg.get_genesis()?,
+    chain_id: &str, cfg: &CosmosChainConfig, output: &OutputArgs, overrides: &Overrides, genesis: &GenesisArgs,
+) -> Result<bool> {
+    let mut cfg = cfg.clone();
+    if let Some(url) = &genesis.genesis_url {
+        if cfg.genesis_url != *url {
+            cfg.genesis_sha256 = "".to_string();
+        }
+        cfg.genesis_url = url.clone();
+    }
+    if let Some(sha256) = &genesis.genesis_sha256 {
+        cfg.genesis_sha256 = sha256.clone();
+    }
+    let (source, download) = genesis_source(&cfg, genesis)?;
+    let path = output_path(output, "genesis.json");
+    // a dry run leaves the output directory untouched
+    let staged = match output.dry_run {
+        true => env::temp_dir().join(format!("starsign-genesis.{}.tmp", process::id())),
+        false => temp_path(&path)?,
     };
-    let path = match output {
-        Some(o) => o.join("genesis.json"),
-        None => PathBuf::new().join("genesis.json"),
+    let hash = write_genesis(source, &staged, overrides)
+        .and_then(|hash| verify_sha256_hex(&hash, &cfg.genesis_sha256));
+    let hash = match hash {
+        Ok(h) => h,
+        Err(err) => {
+            let _ = remove_file(&staged);
+            if let Some(d) = download {
+                d.discard();
+            }
+            return Err(err);
+        },
     };
-    let genesis = match custom {
-        Some(c) => {
-            let patch = from_str(&c)?;
-            let mut base = from_str(&default_genesis)?;
-            merge(&mut base, &patch);
-            info!("customized genesis config");
-            to_string_pretty(&base)?
+    if let Some(d) = download {
+        d.store(&cfg.genesis_url, &hash)?;
+    }
+    if cfg.genesis_sha256.is_empty() {
+        warn!("genesis sha256 is not pinned, computed {}", hash);
+    } else {
+        info!("verified genesis sha256 {}", hash);
+    }
+    if !overrides.is_empty() {
+        info!("customized genesis config");
+    }
+    let mut modules = vec!["auth", "bank", "staking"];
+    if cfg.app.wasm.is_some() {
+        modules.push("wasm");
+    }
+    let issues = match validate_genesis(&staged, Some(chain_id).filter(|c| !c.is_empty()), &modules) {
+        Ok(i) => i,
+        Err(err) => {
+            let _ = remove_file(&staged);
+            return Err(err);
         },
-        None => default_genesis,
     };
-    write_file(&path, &genesis)?;
-    Ok(())
+    if !issues.is_empty() {
+        let report = issues.iter().map(|i| format!("  {}", i)).collect::<Vec<String>>().join("\n");
+        if !genesis.force {
+            let _ = remove_file(&staged);
+            return Err(anyhow!("genesis failed validation, use --force to write it anyway:\n{}", report));
+        }
+        warn!("writing genesis despite validation issues:\n{}", report);
+    }
+    output_staged(output, &staged, &path)
 }
 
 fn config(
-    chain: &Option<String>, output: &Option<PathBuf>, custom: &Option<String>, moniker: &Option<String>, statesync: &bool, statesync_rpc: &Option<String>, statesync_interval: &Option<u64>, genesis_url: &Option<String>, genesis_file: &Option<PathBuf>,
-) -> Result<()> {
-    let customs = match custom {
-        Some(c) => {
-            let patch: Value = from_str(c)?;
-            (
-                patch.get("app").map(to_string).transpose()?,
-                patch.get("tendermint").map(to_string).transpose()?,
-                patch.get("genesis").map(to_string).transpose()?,
-            )
+    chain: &ChainArgs, output: &OutputArgs, overrides: &Overrides, update: bool, tendermint: &TendermintArgs,
+    peers: &PeerArgs, genesis: &GenesisArgs,
+) -> Result<bool> {
+    let sections = ["app", "tendermint", "client", "genesis"];
+    let (chain_id, mut defaults) = chain_config(chain, Some(&tendermint.detect))?;
+    let mut cfg = defaults.clone();
+    let mut updates = vec![];
+    for section in ["app", "tendermint", "client"] {
+        let overrides = with_role(chain.role, section, overrides.section(section, &sections)?);
+        cfg = customize(cfg, section, &overrides)?;
+        updates.push(overrides.paths());
+    }
+    cfg.default_client(&chain_id);
+    defaults.default_client(&chain_id);
+    let mut updates = updates.into_iter().map(|overrides| update.then(|| Update { defaults: defaults.clone(), overrides }));
+    let (app_update, tendermint_update, client_update) = (updates.next().flatten(), updates.next().flatten(), updates.next().flatten());
+    // genesis goes first, the statesync light client reads its validators
+    let genesis = config_genesis(&chain_id, &cfg, output, &overrides.section("genesis", &sections)?, genesis)?;
+    let app = config_app(&cfg, output, app_update.as_ref())?;
+    let client = config_client(&cfg, output, client_update.as_ref())?;
+    let tendermint = config_tendermint(cfg, chain.role, output, tendermint_update, tendermint, peers)?;
+    Ok(app || tendermint || client || genesis)
+}
+
+fn probe_candidates(chain: &ChainArgs, rpc: &Option<String>, peers: &Option<String>) -> Result<Vec<Peer>> {
+    let mut candidates = match peers {
+        Some(p) => parse_peers(p),
+        None => {
+            let (_, cfg) = chain_config(chain, None)?;
+            let mut p = parse_peers(&cfg.tendermint.p2p.persistent_peers);
+            p.extend(parse_peers(&cfg.tendermint.p2p.seeds));
+            p
         },
-        None => (None, None, None)
     };
-    config_app(chain, output, &customs.0)?;
-    config_tendermint(chain, output, &customs.1, moniker, statesync, statesync_rpc, statesync_interval)?;
-    config_genesis(chain, output, &customs.2, genesis_url, genesis_file)?;
-    Ok(())
+    if let Some(r) = rpc {
+        candidates.extend(peers_from_rpc(r)?);
+    }
+    let mut unique: Vec<Peer> = vec![];
+    for c in candidates {
+        if !unique.contains(&c) {
+            unique.push(c);
+        }
+    }
+    Ok(unique)
 }
 
-fn cli_start() -> Result<()> {
-    let cli = Cli::parse();
+fn print_probe_results(candidates: &[Peer], probe: &ProbeArgs) {
+    let results = probe_peers(candidates, &probe.options());
+    println!("{:<4} {:>10}  peer", "rank", "latency");
+    for (i, r) in results.iter().enumerate() {
+        match (&r.latency, &r.error) {
+            (Some(l), None) => println!("{:<4} {:>8.1}ms  {}", i + 1, l.as_secs_f64() * 1000.0, r.peer),
+            (_, e) => println!("{:<4} {:>10}  {} ({})", "-", "-", r.peer, e.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+fn cli_start(cli: Cli) -> Result<bool> {
     match &cli.command {
-        Some(Commands::ConfigApp { chain, output, custom }) => {
-            config_app(chain, output, custom)
+        Some(Commands::ConfigApp { chain, detect, output, overrides, update }) => {
+            let (_, defaults) = chain_config(chain, Some(detect))?;
+            let overrides = with_role(chain.role, "app", overrides.overrides()?);
+            let cfg = customize(defaults.clone(), "app", &overrides)?;
+            let update = update.then(|| Update { defaults, overrides: overrides.paths() });
+            config_app(&cfg, output, update.as_ref())
         },
-        Some(Commands::ConfigTendermint { 
-            chain, output, custom, moniker, statesync, statesync_rpc, statesync_interval,
-        }) => {
-            config_tendermint(chain, output, custom, moniker, statesync, statesync_rpc, statesync_interval)
+        Some(Commands::ConfigTendermint { chain, output, overrides, update, tendermint, peers }) => {
+            let (_, defaults) = chain_config(chain, Some(&tendermint.detect))?;
+            let overrides = with_role(chain.role, "tendermint", overrides.overrides()?);
+            let cfg = customize(defaults.clone(), "tendermint", &overrides)?;
+            let update = update.then(|| Update { defaults, overrides: overrides.paths() });
+            config_tendermint(cfg, chain.role, output, update, tendermint, peers)
         },
-        Some(Commands::ConfigGenesis { chain, output, custom, genesis_url, genesis_file }) => {
-            config_genesis(chain, output, custom, genesis_url, genesis_file)
+        Some(Commands::ConfigClient { chain, output, overrides, update }) => {
+            let (chain_id, mut defaults) = chain_config(chain, None)?;
+            // the node address follows the rpc listener the role sets
+            let cfg = customize(defaults.clone(), "tendermint", &with_role(chain.role, "tendermint", Overrides::default()))?;
+            let overrides = overrides.overrides()?;
+            let mut cfg = customize(cfg, "client", &overrides)?;
+            cfg.default_client(&chain_id);
+            defaults.default_client(&chain_id);
+            let update = update.then(|| Update { defaults, overrides: overrides.paths() });
+            config_client(&cfg, output, update.as_ref())
         },
-        Some(Commands::Config {
-            chain, output, custom, moniker, statesync, statesync_rpc, statesync_interval, genesis_url, genesis_file,
-        }) => {
-            config(chain, output, custom, moniker, statesync, statesync_rpc, statesync_interval, genesis_url, genesis_file)
+        Some(Commands::ConfigGenesis { chain, output, overrides, genesis }) => {
+            let (chain_id, cfg) = chain_config(chain, None)?;
+            config_genesis(&chain_id, &cfg, output, &overrides.overrides()?, genesis)
+        },
+        Some(Commands::Config { chain, output, overrides, update, tendermint, peers, genesis }) => {
+            config(chain, output, &overrides.overrides()?, *update, tendermint, peers, genesis)
+        },
+        Some(Commands::Peers { rpc, peer_count }) => {
+            let discovered = peers_from_rpc(rpc)?;
+            println!("{}", join_peers(&discovered[..discovered.len().min(*peer_count)]));
+            Ok(false)
+        },
+        Some(Commands::ProbePeers { chain, rpc, peers, probe }) => {
+            let candidates = probe_candidates(chain, rpc, peers)?;
+            if candidates.is_empty() {
+                return Err(anyhow!("no peers to probe"));
+            }
+            info!("probing {} peers", candidates.len());
+            print_probe_results(&candidates, probe);
+            Ok(false)
+        },
+        Some(Commands::Rollback { output, backups }) => {
+            let dir = output.clone().unwrap_or_else(|| PathBuf::from("."));
+            for (path, removed) in rollback(&dir, *backups)? {
+                match removed {
+                    true => info!("removed {}", path.to_string_lossy()),
+                    false => info!("restored {}", path.to_string_lossy()),
+                }
+            }
+            Ok(false)
         },
         None => {
             Err(anyhow!("missing command"))
@@ -273,8 +831,137 @@ fn cli_start() -> Result<()> {
     }
 }
 
-fn main() -> Result<()> {
-    env::var("RUST_LOG").err().map(|_| env::set_var("RUST_LOG", "info"));
+/// Exit code as `diff` sets it: 0 when up to date, 1 for pending changes and 2 for errors.
+fn exit_code(result: &Result<bool>) -> i32 {
+    match result {
+        Ok(false) => 0,
+        Ok(true) => 1,
+        Err(_) => 2,
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", if cli.quiet { "warn" } else { "info" });
+    }
     env_logger::init();
-    cli_start().map_err(|err| { error!("configuration failed: {}", err); err })
+    let result = cli_start(cli);
+    match &result {
+        Ok(true) => info!("dry run found pending changes"),
+        Ok(false) => {},
+        Err(err) => {
+            error!("configuration failed: {}", err);
+            eprintln!("Error: {:?}", err);
+        },
+    }
+    process::exit(exit_code(&result));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_dir, write};
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const GENESIS: &str = r#"{"genesis_time": "2023-01-01T00:00:00Z", "chain_id": "kaiyo-1", "initial_height": "1"}"#;
+
+    fn cli(args: &[&str]) -> Result<bool> {
+        cli_start(Cli::try_parse_from([&["starsign"], args].concat())?)
+    }
+
+    fn run(args: &[&str]) -> Result<bool> {
+        cli(&[&["config-genesis", "-c", "kaiyo-1", "--force"], args].concat())
+    }
+
+    #[test]
+    fn genesis_sha256_mismatch_writes_nothing() {
+        let source = tempdir().unwrap();
+        let genesis = source.path().join("genesis.json");
+        write(&genesis, GENESIS).unwrap();
+        let url = format!("file://{}", genesis.to_string_lossy());
+        let wrong = "0".repeat(64);
+        for source_args in [["--genesis-file", &genesis.to_string_lossy()], ["--genesis-url", &url]] {
+            let output = tempdir().unwrap();
+            let output_dir = output.path().to_string_lossy();
+            let err = run(&[&source_args[..], &["-o", &output_dir, "--genesis-sha256", &wrong]].concat()).unwrap_err();
+            assert!(err.to_string().contains(&format!("expected {}", wrong)), "{}", err);
+            assert_eq!(read_dir(output.path()).unwrap().count(), 0);
+        }
+    }
+
+    #[test]
+    fn genesis_sha256_match_writes_genesis() {
+        let output = tempdir().unwrap();
+        let genesis = output.path().join("source.json");
+        write(&genesis, GENESIS).unwrap();
+        let hash = starsign::genesis::sha256_hex(GENESIS.as_bytes());
+        run(&["--genesis-file", &genesis.to_string_lossy(), "-o", &output.path().to_string_lossy(), "--genesis-sha256", &hash])
+            .unwrap();
+        assert!(output.path().join("genesis.json").exists());
+    }
+
+    #[test]
+    fn offline_uses_only_cached_genesis() {
+        let (cache, output) = (tempdir().unwrap(), tempdir().unwrap());
+        let (cache_dir, dir) = (cache.path().to_string_lossy(), output.path().to_string_lossy());
+        // nothing listens on port 1, so a download attempt would fail
+        let url = "http://127.0.0.1:1/genesis.json";
+        let args = ["--genesis-url", url, "--offline", "--cache-dir", &cache_dir, "-o", &dir];
+        let err = run(&args).unwrap_err();
+        assert_eq!(err.to_string(), format!("genesis for {} is not cached and --offline is set", url));
+
+        let (mut tee, download) = GenesisCache::new(cache.path()).download(GENESIS.as_bytes()).unwrap();
+        io::copy(&mut tee, &mut io::sink()).unwrap();
+        let hash = starsign::genesis::sha256_hex(GENESIS.as_bytes());
+        download.store(url, &hash).unwrap();
+        run(&args).unwrap();
+        assert_eq!(read_to_string(output.path().join("genesis.json")).unwrap(), GENESIS);
+        // a pinned hash finds the same genesis under another url
+        let mirror = ["--genesis-url", "http://127.0.0.1:1/mirror.json", "--genesis-sha256", &hash];
+        run(&[&args[2..], &mirror[..]].concat()).unwrap();
+    }
+
+    #[test]
+    fn rollback_restores_the_original_after_quick_successive_runs() {
+        let output = tempdir().unwrap();
+        let dir = output.path().to_string_lossy();
+        let app = output.path().join("app.toml");
+        write(&app, "# original\n").unwrap();
+        cli(&["config-app", "-o", &dir, "--custom", r#"{"pruning": "everything"}"#]).unwrap();
+        let first = read_to_string(&app).unwrap();
+        cli(&["config-app", "-o", &dir, "--custom", r#"{"pruning": "nothing"}"#]).unwrap();
+        cli(&["rollback", "-o", &dir]).unwrap();
+        assert_eq!(read_to_string(&app).unwrap(), first);
+        cli(&["rollback", "-o", &dir]).unwrap();
+        assert_eq!(read_to_string(&app).unwrap(), "# original\n");
+    }
+
+    #[test]
+    fn dry_run_exit_code_tells_pending_changes_from_errors() {
+        let output = tempdir().unwrap();
+        let genesis = output.path().join("source.json");
+        write(&genesis, GENESIS).unwrap();
+        let out = tempdir().unwrap();
+        let (source, dir) = (genesis.to_string_lossy(), out.path().to_string_lossy());
+        let args = ["--genesis-file", &source, "-o", &dir, "--dry-run"];
+        assert_eq!(exit_code(&run(&args)), 1);
+        // the dry run staged genesis outside the output directory
+        assert_eq!(read_dir(out.path()).unwrap().count(), 0);
+        run(&args[..4]).unwrap();
+        assert_eq!(exit_code(&run(&args)), 0);
+        assert_eq!(exit_code(&run(&[&args[..], &["--genesis-sha256", "00"]].concat())), 2);
+    }
+
+    #[test]
+    fn only_rendering_commands_detect_versions() {
+        for command in ["config-app", "config-tendermint", "config"] {
+            assert!(Cli::try_parse_from(["starsign", command, "--detect-versions"]).is_ok(), "{}", command);
+        }
+        for command in ["config-client", "config-genesis", "probe-peers"] {
+            assert!(Cli::try_parse_from(["starsign", command, "--detect-versions"]).is_err(), "{}", command);
+        }
+    }
 }
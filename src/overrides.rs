@@ -0,0 +1,528 @@
+use std::fs::read_to_string;
+use std::io::{stdin, Read};
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use json_patch::{merge, patch, PatchOperation};
+use log::warn;
+use serde_json::{from_str, Map, Value};
+
+/// `--set` override of a dot-separated path.
+#[derive(Clone)]
+pub struct SetOverride {
+    pub path: Vec<String>,
+    pub value: String,
+}
+
+impl SetOverride {
+    pub fn parse(s: &str) -> Result<Self> {
+        let (path, value) = s.split_once('=').ok_or(anyhow!("--set {} must be <path>=<value>", s))?;
+        let path: Vec<String> = path.trim().split('.').map(str::to_string).collect();
+        if path.iter().any(|p| p.is_empty()) {
+            return Err(anyhow!("--set {} has an empty path segment", s));
+        }
+        Ok(Self { path, value: value.to_string() })
+    }
+
+    /// Infers the value's type from its text, keeping strings where the existing value is a string.
+    pub fn typed_value(&self, existing: Option<&Value>) -> Value {
+        match from_str::<Value>(&self.value) {
+            Ok(v) if existing.is_some_and(|e| e.is_string()) && !v.is_string() => Value::String(self.value.clone()),
+            Ok(v) => v,
+            Err(_) => Value::String(self.value.clone()),
+        }
+    }
+
+    /// Steps into `key` of `target`, creating the object, or the array for an index, where it is missing.
+    fn step<'a>(&self, target: &'a mut Value, key: &str, at: &[String]) -> Result<&'a mut Value> {
+        let at = if at.is_empty() { "the document".to_string() } else { at.join(".") };
+        let index = key.parse::<usize>().ok();
+        if target.is_null() {
+            *target = if index.is_some() { Value::Array(vec![]) } else { Value::Object(Map::new()) };
+        }
+        match target {
+            Value::Array(items) => {
+                let i = index.ok_or(anyhow!("cannot set {}: {} is an array, expected an index", self.path.join("."), at))?;
+                if i == items.len() {
+                    items.push(Value::Null);
+                }
+                let len = items.len();
+                items.get_mut(i).ok_or(anyhow!(
+                    "cannot set {}: index {} is past the end of {}, which has {} items", self.path.join("."), i, at, len,
+                ))
+            },
+            Value::Object(members) => Ok(members.entry(key).or_insert(Value::Null)),
+            _ => Err(anyhow!("cannot set {}: {} is not an object or array", self.path.join("."), at)),
+        }
+    }
+
+    fn apply(&self, doc: &mut Value) -> Result<()> {
+        let (last, parents) = self.path.split_last().ok_or(anyhow!("empty --set path"))?;
+        let mut target = doc;
+        for (i, segment) in parents.iter().enumerate() {
+            target = self.step(target, segment, &self.path[..i])?;
+        }
+        // keep the type of the value being replaced, before step creates a null in its place
+        let value = self.typed_value(match &*target {
+            Value::Array(items) => last.parse::<usize>().ok().and_then(|i| items.get(i)),
+            Value::Object(members) => members.get(last),
+            _ => None,
+        });
+        *self.step(target, last, parents)? = value;
+        Ok(())
+    }
+}
+
+/// Overrides folded into one merge patch, keeping the `--set` values whose type depends on the value they replace.
+pub struct StreamPatch {
+    pub merge: Value,
+    pub sets: Vec<SetOverride>,
+}
+
+/// Overrides applied in order: merge patches (RFC 7396), then JSON patches (RFC 6902), then `--set` values.
+#[derive(Clone, Default)]
+pub struct Overrides {
+    pub merges: Vec<Value>,
+    pub patches: Vec<PatchOperation>,
+    pub sets: Vec<SetOverride>,
+    /// Warn about unknown keys instead of failing.
+    pub lenient: bool,
+}
+
+fn op_paths(op: &mut PatchOperation) -> (&mut String, Option<&mut String>) {
+    match op {
+        PatchOperation::Add(o) => (&mut o.path, None),
+        PatchOperation::Remove(o) => (&mut o.path, None),
+        PatchOperation::Replace(o) => (&mut o.path, None),
+        PatchOperation::Move(o) => (&mut o.path, Some(&mut o.from)),
+        PatchOperation::Copy(o) => (&mut o.path, Some(&mut o.from)),
+        PatchOperation::Test(o) => (&mut o.path, None),
+    }
+}
+
+/// Splits `/<section>/rest` into the section and `/rest`.
+fn split_pointer(pointer: &str) -> Option<(&str, String)> {
+    let rest = pointer.strip_prefix('/')?;
+    match rest.split_once('/') {
+        Some((section, rest)) => Some((section, format!("/{}", rest))),
+        None => Some((rest, String::new())),
+    }
+}
+
+/// Reads a `--custom` merge patch given inline, as `@path` to a json, yaml or toml file, or as `@-` for stdin.
+fn read_custom(custom: &str, stdin: impl Read) -> Result<Value> {
+    let path = match custom.strip_prefix('@') {
+        Some(p) => p,
+        None => return from_str(custom).map_err(|err| anyhow!("invalid --custom json: {}", err)),
+    };
+    if path == "-" {
+        let mut text = String::new();
+        { stdin }.read_to_string(&mut text)?;
+        return from_str(&text).map_err(|err| anyhow!("invalid --custom json on stdin: {}", err));
+    }
+    let text = read_to_string(path).map_err(|err| anyhow!("failed to read --custom {}: {}", path, err))?;
+    let extension = Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("yaml" | "yml") => serde_yaml::from_str(&text).map_err(|err| anyhow!("invalid --custom yaml {}: {}", path, err)),
+        Some("toml") => toml::from_str(&text).map_err(|err| anyhow!("invalid --custom toml {}: {}", path, err)),
+        _ => from_str(&text).map_err(|err| anyhow!("invalid --custom json {}: {}", path, err)),
+    }
+}
+
+impl Overrides {
+    pub fn parse(custom: &[String], patch: &[String], set: &[String]) -> Result<Self> {
+        if custom.iter().filter(|c| *c == "@-").count() > 1 {
+            return Err(anyhow!("--custom @- can only be given once"));
+        }
+        let merges = custom.iter().map(|c| read_custom(c, stdin())).collect::<Result<Vec<Value>>>()?;
+        // overrides of the whole document would replace every key
+        if let Some((c, _)) = custom.iter().zip(&merges).find(|(_, m)| !m.is_object()) {
+            return Err(anyhow!("--custom {} must be an object", c));
+        }
+        let mut patches = vec![];
+        for p in patch {
+            let ops: Vec<PatchOperation> = from_str(p).map_err(|err| anyhow!("invalid --patch operations {}: {}", p, err))?;
+            patches.extend(ops);
+        }
+        if patches.iter_mut().any(|op| op_paths(op).0.is_empty()) {
+            return Err(anyhow!("--patch paths must not be empty"));
+        }
+        let sets = set.iter().map(|s| SetOverride::parse(s)).collect::<Result<Vec<SetOverride>>>()?;
+        Ok(Self { merges, patches, sets, lenient: false })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.merges.is_empty() && self.patches.is_empty() && self.sets.is_empty()
+    }
+
+    pub fn apply(&self, doc: &mut Value) -> Result<()> {
+        for m in &self.merges {
+            merge(doc, m);
+        }
+        patch(doc, &self.patches)?;
+        for s in &self.sets {
+            s.apply(doc)?;
+        }
+        Ok(())
+    }
+
+    /// Overrides for one section of the combined `{"app", "tendermint", "client", "genesis"}` document.
+    pub fn section(&self, name: &str, sections: &[&str]) -> Result<Self> {
+        let merges: Vec<Value> = self.merges.iter().filter_map(|m| m.get(name).cloned()).collect();
+        if merges.iter().any(|m| !m.is_object()) {
+            return Err(anyhow!("--custom {} must be an object", name));
+        }
+        let mut patches = vec![];
+        for op in &self.patches {
+            let mut op = op.clone();
+            let (path, from) = op_paths(&mut op);
+            let (section, rest) = split_pointer(path)
+                .filter(|(s, _)| sections.contains(s))
+                .ok_or(anyhow!("--patch path {} must start with one of /{}", path, sections.join(", /")))?;
+            if rest.is_empty() {
+                return Err(anyhow!("--patch path {} must not replace the whole section", path));
+            }
+            if section != name {
+                continue;
+            }
+            *path = rest;
+            if let Some(from) = from {
+                let (from_section, from_rest) = split_pointer(from)
+                    .ok_or(anyhow!("invalid --patch from {}", from))?;
+                if from_section != name {
+                    return Err(anyhow!("--patch cannot move or copy between {} and {}", from_section, name));
+                }
+                *from = from_rest;
+            }
+            patches.push(op);
+        }
+        let mut sets = vec![];
+        for s in &self.sets {
+            if !sections.contains(&s.path[0].as_str()) || s.path.len() < 2 {
+                return Err(anyhow!("--set path {} must start with one of {}", s.path.join("."), sections.join(", ")));
+            }
+            if s.path[0] == name {
+                sets.push(SetOverride { path: s.path[1..].to_vec(), value: s.value.clone() });
+            }
+        }
+        Ok(Self { merges, patches, sets, lenient: self.lenient })
+    }
+
+    /// Key paths the overrides write to.
+    pub fn paths(&self) -> Vec<Vec<String>> {
+        let mut paths = vec![];
+        for m in &self.merges {
+            leaf_paths(m, &mut vec![], &mut paths);
+        }
+        for op in &self.patches {
+            if let PatchOperation::Test(_) = op {
+                continue;
+            }
+            let mut op = op.clone();
+            let (path, _) = op_paths(&mut op);
+            paths.push(path.split('/').skip(1).map(|s| s.replace("~1", "/").replace("~0", "~")).collect());
+        }
+        paths.extend(self.sets.iter().map(|s| s.path.clone()));
+        paths
+    }
+
+    /// Checks that every key of the overridden `doc` survived deserializing it into `parsed`.
+    pub fn check_keys(&self, doc: &Value, parsed: &Value, prefix: &str) -> Result<()> {
+        let mut unknown = vec![];
+        dropped_keys(doc, parsed, prefix, &mut unknown);
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        let unknown = unknown.join(", ");
+        if self.lenient {
+            warn!("ignoring unknown keys: {}", unknown);
+            Ok(())
+        } else {
+            Err(anyhow!("unknown keys: {} (use --lenient to ignore them)", unknown))
+        }
+    }
+
+    /// A single merge patch with the same effect, if one exists, so large documents can be patched while streaming.
+    pub fn as_stream_patch(&self) -> Option<StreamPatch> {
+        if !self.patches.is_empty() {
+            return None;
+        }
+        let mut combined = Value::Object(Map::new());
+        for m in &self.merges {
+            combined = compose(combined, m)?;
+        }
+        for s in &self.sets {
+            let value = s.typed_value(None);
+            if value.is_null() || value.is_object() || s.path.iter().any(|p| p.parse::<usize>().is_ok()) {
+                return None;
+            }
+            let set_patch = s.path.iter().rev().fold(value, |v, key| {
+                let mut members = Map::new();
+                members.insert(key.clone(), v);
+                Value::Object(members)
+            });
+            combined = compose(combined, &set_patch)?;
+        }
+        Some(StreamPatch { merge: combined, sets: self.sets.clone() })
+    }
+}
+
+/// Combines two merge patches into one equivalent to applying `first` then `second`, where that is possible.
+fn compose(first: Value, second: &Value) -> Option<Value> {
+    let second_members = match second {
+        Value::Object(m) => m,
+        _ => return Some(second.clone()),
+    };
+    let mut members = match first {
+        Value::Object(m) => m,
+        _ => return None,
+    };
+    for (key, value) in second_members {
+        let combined = match (members.remove(key), value) {
+            (_, Value::Null) => Value::Null,
+            (Some(Value::Null), v) if v.is_object() => return None,
+            (Some(existing), v) => compose(existing, v)?,
+            (None, v) => v.clone(),
+        };
+        members.insert(key.clone(), combined);
+    }
+    Some(Value::Object(members))
+}
+
+/// Collects the paths of the values a merge patch sets or removes.
+fn leaf_paths(patch: &Value, path: &mut Vec<String>, paths: &mut Vec<Vec<String>>) {
+    match patch {
+        Value::Object(members) => {
+            for (key, value) in members {
+                path.push(key.clone());
+                leaf_paths(value, path, paths);
+                path.pop();
+            }
+        },
+        _ => paths.push(path.clone()),
+    }
+}
+
+/// Collects keys of `doc` missing from `parsed`, each with the closest key that exists alongside it.
+fn dropped_keys(doc: &Value, parsed: &Value, prefix: &str, unknown: &mut Vec<String>) {
+    match (doc, parsed) {
+        (Value::Object(doc), Value::Object(parsed)) => {
+            for (key, value) in doc {
+                let path = format!("{}.{}", prefix, key);
+                match parsed.get(key) {
+                    Some(p) => dropped_keys(value, p, &path, unknown),
+                    None if value.is_null() => {},
+                    None => {
+                        let closest = parsed.keys()
+                            .map(|k| (strsim::damerau_levenshtein(key, k), k))
+                            .filter(|(distance, k)| *distance <= (k.len() / 3).max(1))
+                            .min();
+                        unknown.push(match closest {
+                            Some((_, k)) => format!("{} (did you mean {}.{}?)", path, prefix, k),
+                            None => path,
+                        });
+                    },
+                }
+            }
+        },
+        (Value::Array(doc), Value::Array(parsed)) => {
+            for (i, (d, p)) in doc.iter().zip(parsed).enumerate() {
+                dropped_keys(d, p, &format!("{}.{}", prefix, i), unknown);
+            }
+        },
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+    use std::io::{self, Cursor};
+
+    use serde_json::{json, to_value};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::config::{default_wasmd_config, CosmosAppConfig};
+
+    fn parse(custom: &[&str], patch: &[&str], set: &[&str]) -> Result<Overrides> {
+        let strings = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<String>>();
+        Overrides::parse(&strings(custom), &strings(patch), &strings(set))
+    }
+
+    #[test]
+    fn composed_merge_patches_match_sequential_merges() {
+        let docs = [json!(null), json!(5), json!({}), json!({"a": {"b": 1, "c": [1]}, "d": "e"}), json!({"a": 1, "d": {"x": 2}})];
+        let patches = [
+            json!({}), json!({"a": {"b": null}}), json!({"a": {"c": 3, "b": {"n": 1}}}), json!({"a": null}), json!({"a": 1}),
+            json!({"d": {"x": null, "y": {"z": null}}}), json!({"a": {"b": {}}}), json!("scalar"),
+        ];
+        let mut composed = 0;
+        for first in &patches {
+            for second in &patches {
+                let patch = match compose(first.clone(), second) {
+                    Some(p) => p,
+                    None => continue,
+                };
+                composed += 1;
+                for doc in &docs {
+                    let mut sequential = doc.clone();
+                    merge(&mut sequential, first);
+                    merge(&mut sequential, second);
+                    let mut combined = doc.clone();
+                    merge(&mut combined, &patch);
+                    assert_eq!(combined, sequential, "{} then {} on {}", first, second, doc);
+                }
+            }
+        }
+        assert!(composed > patches.len() * patches.len() / 2);
+    }
+
+    #[test]
+    fn paths_cover_every_override() {
+        let overrides = parse(
+            &[r#"{"p2p": {"seeds": "", "pex": null}, "empty": {}}"#],
+            &[r#"[{"op": "replace", "path": "/rpc/cors~1origins", "value": []}, {"op": "test", "path": "/x", "value": 1}]"#],
+            &["moniker=node"],
+        ).unwrap();
+        assert_eq!(overrides.paths(), vec![
+            vec!["p2p".to_string(), "seeds".to_string()],
+            vec!["p2p".to_string(), "pex".to_string()],
+            vec!["rpc".to_string(), "cors/origins".to_string()],
+            vec!["moniker".to_string()],
+        ]);
+    }
+
+    #[test]
+    fn rejects_overrides_of_the_whole_document() {
+        let err = |custom: &[&str], patch: &[&str]| parse(custom, patch, &[]).err().map(|e| e.to_string());
+        assert_eq!(err(&["5"], &[]).as_deref(), Some("--custom 5 must be an object"));
+        assert_eq!(err(&["null"], &[]).as_deref(), Some("--custom null must be an object"));
+        assert_eq!(err(&[], &[r#"[{"op": "add", "path": "", "value": {}}]"#]).as_deref(), Some("--patch paths must not be empty"));
+
+        let sections = ["app", "tendermint"];
+        let section = |custom: &str, patch: &[&str]| {
+            parse(&[custom], patch, &[]).unwrap().section("app", &sections).err().map(|e| e.to_string())
+        };
+        assert_eq!(section(r#"{"app": "x"}"#, &[]).as_deref(), Some("--custom app must be an object"));
+        assert_eq!(section(r#"{"tendermint": null}"#, &[]), None);
+        assert_eq!(
+            section("{}", &[r#"[{"op": "remove", "path": "/tendermint"}]"#]).as_deref(),
+            Some("--patch path /tendermint must not replace the whole section"),
+        );
+    }
+
+    #[test]
+    fn applies_repeated_patches_in_order() {
+        let overrides = parse(&[], &[
+            r#"[{"op": "add", "path": "/peers", "value": []}]"#,
+            r#"[{"op": "add", "path": "/peers/-", "value": "a"}, {"op": "add", "path": "/peers/-", "value": "b"}]"#,
+            r#"[{"op": "move", "from": "/peers/0", "path": "/peers/-"}]"#,
+        ], &[]).unwrap();
+        let mut doc = json!({});
+        overrides.apply(&mut doc).unwrap();
+        assert_eq!(doc, json!({"peers": ["b", "a"]}));
+        let err = parse(&[], &["[]", "{}"], &[]).err().unwrap().to_string();
+        assert!(err.starts_with("invalid --patch operations {}:"), "{}", err);
+    }
+
+    #[test]
+    fn sets_through_array_indices() {
+        let set = |doc: Value, path: &str| -> Result<Value> {
+            let mut doc = doc;
+            parse(&[], &[], &[path])?.apply(&mut doc)?;
+            Ok(doc)
+        };
+        assert_eq!(set(json!({}), "a.0.b=1").unwrap(), json!({"a": [{"b": 1}]}));
+        assert_eq!(set(json!({"a": []}), "a.0.b=1").unwrap(), json!({"a": [{"b": 1}]}));
+        assert_eq!(set(json!({"a": [{"b": "0", "c": 2}]}), "a.0.b=1").unwrap(), json!({"a": [{"b": "1", "c": 2}]}));
+        assert_eq!(set(json!({"a": [1]}), "a.1=2").unwrap(), json!({"a": [1, 2]}));
+        assert_eq!(set(json!({"a": [[]]}), "a.0.0.x=y").unwrap(), json!({"a": [[{"x": "y"}]]}));
+        assert_eq!(
+            set(json!({"a": []}), "a.1.b=1").unwrap_err().to_string(),
+            "cannot set a.1.b: index 1 is past the end of a, which has 0 items",
+        );
+        assert_eq!(set(json!({"a": []}), "a.b=1").unwrap_err().to_string(), "cannot set a.b: a is an array, expected an index");
+        assert_eq!(set(json!({"a": "x"}), "a.b=1").unwrap_err().to_string(), "cannot set a.b: a is not an object or array");
+        assert_eq!(set(json!(5), "a=1").unwrap_err().to_string(), "cannot set a: the document is not an object or array");
+    }
+
+    #[test]
+    fn reads_custom_files_by_extension() {
+        let dir = tempdir().unwrap();
+        let files = [
+            ("patch.json", r#"{"pruning": "custom", "halt_height": 5, "api": {"enable": true, "address": "tcp://0.0.0.0:1317"}}"#),
+            ("patch", r#"{"pruning": "custom", "halt_height": 5, "api": {"enable": true, "address": "tcp://0.0.0.0:1317"}}"#),
+            ("patch.yaml", "pruning: custom\nhalt_height: 5\napi:\n  enable: true\n  address: tcp://0.0.0.0:1317\n"),
+            ("patch.YML", "pruning: custom\nhalt_height: 5\napi: {enable: true, address: \"tcp://0.0.0.0:1317\"}\n"),
+            ("patch.toml", "pruning = \"custom\"\nhalt_height = 5\n[api]\nenable = true\naddress = \"tcp://0.0.0.0:1317\"\n"),
+        ];
+        let expected = json!({"pruning": "custom", "halt_height": 5, "api": {"enable": true, "address": "tcp://0.0.0.0:1317"}});
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            write(&path, content).unwrap();
+            let custom = format!("@{}", path.to_string_lossy());
+            assert_eq!(read_custom(&custom, io::empty()).unwrap(), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn reads_custom_from_stdin() {
+        let custom = read_custom("@-", Cursor::new(r#"{"pruning": "everything"}"#)).unwrap();
+        assert_eq!(custom, json!({"pruning": "everything"}));
+        let err = read_custom("@-", Cursor::new("pruning: everything")).unwrap_err().to_string();
+        assert!(err.starts_with("invalid --custom json on stdin: "), "{}", err);
+        let err = parse(&["@-", "@-"], &[], &[]).err().unwrap().to_string();
+        assert_eq!(err, "--custom @- can only be given once");
+    }
+
+    #[test]
+    fn reports_unreadable_custom_files() {
+        let dir = tempdir().unwrap();
+        let missing = dir.path().join("missing.yaml").to_string_lossy().to_string();
+        let err = read_custom(&format!("@{}", missing), io::empty()).unwrap_err().to_string();
+        assert!(err.starts_with(&format!("failed to read --custom {}: ", missing)), "{}", err);
+        for (name, content, format) in [("bad.yaml", "a: [", "yaml"), ("bad.toml", "a = ", "toml"), ("bad.json", "{", "json")] {
+            let path = dir.path().join(name);
+            write(&path, content).unwrap();
+            let err = read_custom(&format!("@{}", path.to_string_lossy()), io::empty()).unwrap_err().to_string();
+            assert!(err.starts_with(&format!("invalid --custom {} {}: ", format, path.to_string_lossy())), "{}", err);
+        }
+        // a yaml list is valid yaml but not a merge patch
+        let list = dir.path().join("list.yaml");
+        write(&list, "- pruning\n").unwrap();
+        let custom = format!("@{}", list.to_string_lossy());
+        assert_eq!(parse(&[&custom], &[], &[]).err().unwrap().to_string(), format!("--custom {} must be an object", custom));
+    }
+
+    fn unknown_keys(overrides: &Overrides) -> Result<()> {
+        let mut doc = to_value(default_wasmd_config().app).unwrap();
+        overrides.apply(&mut doc).unwrap();
+        let parsed: CosmosAppConfig = serde_json::from_value(doc.clone()).unwrap();
+        overrides.check_keys(&doc, &to_value(parsed).unwrap(), "app")
+    }
+
+    #[test]
+    fn suggests_the_closest_known_key() {
+        let overrides = parse(
+            &[r#"{"prunning": "nothing", "api": {"enabel": true}, "grpc": {"address": "0.0.0.0:9090"}, "telemetry": {"x": null}}"#],
+            &[r#"[{"op": "add", "path": "/totally_unknown", "value": 1}]"#],
+            &["index_events.0=tx.height", "state_sync.snapshot_keep=2"],
+        ).unwrap();
+        // only typos get a suggestion, not keys that are merely part of a known one
+        assert_eq!(
+            unknown_keys(&overrides).unwrap_err().to_string(),
+            "unknown keys: app.api.enabel (did you mean app.api.enable?), \
+             app.state_sync.snapshot_keep, \
+             app.prunning (did you mean app.pruning?), app.totally_unknown (use --lenient to ignore them)",
+        );
+        assert!(unknown_keys(&Overrides { lenient: true, ..overrides }).is_ok());
+    }
+
+    #[test]
+    fn accepts_known_and_removed_keys() {
+        let overrides = parse(&[r#"{"pruning": "nothing", "wasm": null, "api": {"enable": true}}"#], &[], &["index_events.0=tx.height"])
+            .unwrap();
+        assert!(unknown_keys(&overrides).is_ok());
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+
+use crate::rpc::BlockingRpc;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "{}@[{}]:{}", self.id, self.host, self.port)
+        } else {
+            write!(f, "{}@{}:{}", self.id, self.host, self.port)
+        }
+    }
+}
+
+impl FromStr for Peer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (id, addr) = s.trim().split_once('@').ok_or(anyhow!("peer {} must be <id>@<host>:<port>", s))?;
+        let (host, port) = addr.rsplit_once(':').ok_or(anyhow!("peer {} has no port", s))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if id.is_empty() || host.is_empty() {
+            return Err(anyhow!("peer {} must be <id>@<host>:<port>", s));
+        }
+        Ok(Peer { id: id.to_string(), host: host.to_string(), port: port.parse()? })
+    }
+}
+
+fn is_routable_v4(ip: &Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || octets[0] == 0
+        || (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+        || (octets[0] == 198 && (octets[1] & 0xfe) == 18)
+        || octets[0] >= 240)
+}
+
+fn is_routable_v6(ip: &Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || (segments[0] & 0xfe00) == 0xfc00
+        || (segments[0] & 0xffc0) == 0xfe80
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+}
+
+/// Whether an address is publicly reachable, excluding private, loopback and reserved ranges.
+pub fn is_routable(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_routable_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_routable_v4(&v4),
+            None => is_routable_v6(ip),
+        },
+    }
+}
+
+fn listen_port(listen_addr: &str) -> Option<u16> {
+    listen_addr.rsplit(':').next()?.parse().ok()
+}
+
+/// Formats peers for `persistent_peers` or `seeds`.
+pub fn join_peers(peers: &[Peer]) -> String {
+    peers.iter().map(|p| p.to_string()).collect::<Vec<String>>().join(",")
+}
+
+/// Parses a comma-separated `persistent_peers` or `seeds` list, skipping invalid entries.
+pub fn parse_peers(peers: &str) -> Vec<Peer> {
+    peers.split(',')
+        .filter(|p| !p.trim().is_empty())
+        .filter_map(|p| match p.parse() {
+            Ok(peer) => Some(peer),
+            Err(err) => {
+                warn!("skipping invalid peer: {}", err);
+                None
+            },
+        })
+        .collect()
+}
+
+/// Collects routable, deduplicated peers connected to an rpc node.
+pub fn peers_from_rpc(rpc_url: &str) -> Result<Vec<Peer>> {
+    let net_info = BlockingRpc::from_url(rpc_url)?.net_info()?;
+    let mut peers: Vec<Peer> = vec![];
+    for p in net_info.peers {
+        let port = match listen_port(p.node_info.listen_addr.as_str()) {
+            Some(port) => port,
+            None => continue,
+        };
+        if !is_routable(&p.remote_ip) {
+            continue;
+        }
+        let peer = Peer { id: p.node_info.id.to_string(), host: p.remote_ip.to_string(), port };
+        if peers.iter().any(|x| x.id == peer.id || (x.host == peer.host && x.port == peer.port)) {
+            continue;
+        }
+        peers.push(peer);
+    }
+    info!("found {} usable peers from {}", peers.len(), rpc_url);
+    Ok(peers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn monitor() -> Value {
+        json!({
+            "Active": true, "Start": "2023-01-01T00:00:00Z", "Duration": "1000000000", "Idle": "0",
+            "Bytes": "0", "Samples": "0", "InstRate": "0", "CurRate": "0", "AvgRate": "0", "PeakRate": "0",
+            "BytesRem": "0", "TimeRem": "0", "Progress": 0,
+        })
+    }
+
+    fn peer_info(id: &str, remote_ip: &str, listen_addr: &str) -> Value {
+        json!({
+            "node_info": {
+                "protocol_version": { "p2p": "8", "block": "11", "app": "1" },
+                "id": id,
+                "listen_addr": listen_addr,
+                "network": "mock-1",
+                "version": "0.34.21",
+                "channels": "40202122233038606100",
+                "moniker": "mock",
+                "other": { "tx_index": "on", "rpc_address": "tcp://0.0.0.0:26657" },
+            },
+            "is_outbound": false,
+            "connection_status": { "Duration": "1000000000", "SendMonitor": monitor(), "RecvMonitor": monitor(), "Channels": [] },
+            "remote_ip": remote_ip,
+        })
+    }
+
+    /// Serves one net_info response with `peers` and returns the rpc url.
+    fn serve_net_info(peers: Vec<Value>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let request: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(request["method"], "net_info");
+            let response = json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "result": { "listening": true, "listeners": [], "n_peers": peers.len().to_string(), "peers": peers },
+            }).to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(), response,
+            ).unwrap();
+        });
+        url
+    }
+
+    const ID_A: &str = "0b8e9b2fc583439e365a85f06a8909294f7657e8";
+    const ID_B: &str = "1b8e9b2fc583439e365a85f06a8909294f7657e8";
+    const ID_C: &str = "2b8e9b2fc583439e365a85f06a8909294f7657e8";
+    const ID_D: &str = "3b8e9b2fc583439e365a85f06a8909294f7657e8";
+
+    #[test]
+    fn discovers_routable_unique_peers() {
+        let url = serve_net_info(vec![
+            peer_info(ID_A, "8.8.8.8", "tcp://0.0.0.0:26656"),
+            // private, unparseable port, repeated id and repeated address are dropped
+            peer_info(ID_B, "10.0.0.5", "tcp://0.0.0.0:26656"),
+            peer_info(ID_B, "1.1.1.1", "tcp://0.0.0.0:p2p"),
+            peer_info(ID_A, "9.9.9.9", "tcp://0.0.0.0:26656"),
+            peer_info(ID_C, "8.8.8.8", "26656"),
+            // the port comes from the listen address, the host from the connection
+            peer_info(ID_D, "2606:4700::1111", "tcp://127.0.0.1:36656"),
+        ]);
+        let peers = peers_from_rpc(&url).unwrap();
+        assert_eq!(join_peers(&peers), format!("{}@8.8.8.8:26656,{}@[2606:4700::1111]:36656", ID_A, ID_D));
+    }
+
+    #[test]
+    fn parses_and_formats_peers() {
+        let peers = parse_peers(" a@1.2.3.4:26656, b@[2606:4700::1111]:26656,,bad,c@host:port,@x:1,d@seed.example.com:26656");
+        assert_eq!(join_peers(&peers), "a@1.2.3.4:26656,b@[2606:4700::1111]:26656,d@seed.example.com:26656");
+        assert_eq!(peers[1].host, "2606:4700::1111");
+    }
+
+    #[test]
+    fn tells_routable_addresses() {
+        for ip in ["8.8.8.8", "100.128.0.1", "2606:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(is_routable(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["10.1.2.3", "172.16.0.1", "192.168.1.1", "127.0.0.1", "169.254.1.1", "0.0.0.0", "100.64.0.1", "198.18.0.1",
+            "240.0.0.1", "255.255.255.255", "192.0.2.1", "224.0.0.1", "::1", "::", "fd00::1", "fe80::1", "2001:db8::1",
+            "::ffff:10.0.0.1"] {
+            assert!(!is_routable(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow};
+use chacha20poly1305::aead::{AeadInPlace, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use hkdf::Hkdf;
+use log::{info, warn};
+use merlin::Transcript;
+use prost::Message;
+use sha2::Sha256;
+use tendermint::{account, PublicKey, Signature};
+use x25519_dalek::{PublicKey as EphemeralPublic, StaticSecret};
+
+use crate::peers::Peer;
+
+const DATA_LEN_SIZE: usize = 4;
+const DATA_MAX_SIZE: usize = 1024;
+const TAG_SIZE: usize = 16;
+const SEALED_FRAME_SIZE: usize = DATA_LEN_SIZE + DATA_MAX_SIZE + TAG_SIZE;
+
+#[derive(Clone, PartialEq, Message)]
+struct BytesValue {
+    #[prost(bytes = "vec", tag = "1")]
+    value: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoPublicKey {
+    #[prost(bytes = "vec", tag = "1")]
+    ed25519: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AuthSigMessage {
+    #[prost(message, optional, tag = "1")]
+    pub_key: Option<ProtoPublicKey>,
+    #[prost(bytes = "vec", tag = "2")]
+    sig: Vec<u8>,
+}
+
+pub struct ProbeOptions {
+    pub timeout: Duration,
+    pub concurrency: usize,
+    pub verify_node_id: bool,
+}
+
+pub struct ProbeResult {
+    pub peer: Peer,
+    pub latency: Option<Duration>,
+    pub error: Option<String>,
+}
+
+impl ProbeResult {
+    pub fn is_reachable(&self) -> bool {
+        self.latency.is_some() && self.error.is_none()
+    }
+}
+
+fn read_varint(stream: &mut impl Read) -> Result<usize> {
+    let mut value = 0usize;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("invalid varint"))
+}
+
+/// Reads one sealed secret connection frame and returns its plaintext.
+fn read_frame(stream: &mut impl Read, cipher: &ChaCha20Poly1305, nonce: &mut u64) -> Result<Vec<u8>> {
+    let mut frame = [0u8; SEALED_FRAME_SIZE];
+    stream.read_exact(&mut frame)?;
+    let mut nonce_bytes = [0u8; 12];
+    nonce_bytes[4..].copy_from_slice(&nonce.to_le_bytes());
+    *nonce += 1;
+    let (data, tag) = frame.split_at_mut(DATA_LEN_SIZE + DATA_MAX_SIZE);
+    cipher.decrypt_in_place_detached(Nonce::from_slice(&nonce_bytes), b"", data, Tag::from_slice(tag))
+        .map_err(|_| anyhow!("secret connection frame failed to decrypt"))?;
+    let len = u32::from_le_bytes(data[..DATA_LEN_SIZE].try_into()?) as usize;
+    if len > DATA_MAX_SIZE {
+        return Err(anyhow!("secret connection frame too large"));
+    }
+    Ok(data[DATA_LEN_SIZE..DATA_LEN_SIZE + len].to_vec())
+}
+
+/// Runs the Tendermint secret connection handshake far enough to authenticate the remote node and returns its id.
+fn handshake_node_id(stream: &mut (impl Read + Write)) -> Result<String> {
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed).map_err(|err| anyhow!("{}", err))?;
+    authenticate(stream, StaticSecret::from(seed))
+}
+
+/// Runs the handshake with a given ephemeral secret, which lets tests replay a recorded handshake.
+fn authenticate(stream: &mut (impl Read + Write), local_secret: StaticSecret) -> Result<String> {
+    let local_public = EphemeralPublic::from(&local_secret);
+    stream.write_all(&BytesValue { value: local_public.as_bytes().to_vec() }.encode_length_delimited_to_vec())?;
+
+    let mut remote_msg = vec![0u8; read_varint(stream)?];
+    stream.read_exact(&mut remote_msg)?;
+    let remote_bytes: [u8; 32] = BytesValue::decode(remote_msg.as_slice())?
+        .value
+        .try_into()
+        .map_err(|_| anyhow!("invalid ephemeral key"))?;
+    let remote_public = EphemeralPublic::from(remote_bytes);
+    let shared = local_secret.diffie_hellman(&remote_public);
+    if shared.as_bytes() == &[0u8; 32] {
+        return Err(anyhow!("low order ephemeral key"));
+    }
+
+    let local_is_low = local_public.as_bytes() < remote_public.as_bytes();
+    let (low, high) = if local_is_low { (&local_public, &remote_public) } else { (&remote_public, &local_public) };
+    let mut transcript = Transcript::new(b"TENDERMINT_SECRET_CONNECTION_TRANSCRIPT_HASH");
+    transcript.append_message(b"EPHEMERAL_LOWER_PUBLIC_KEY", low.as_bytes());
+    transcript.append_message(b"EPHEMERAL_UPPER_PUBLIC_KEY", high.as_bytes());
+    transcript.append_message(b"DH_SECRET", shared.as_bytes());
+    let mut challenge = [0u8; 32];
+    transcript.challenge_bytes(b"SECRET_CONNECTION_MAC", &mut challenge);
+
+    let mut key_material = [0u8; 96];
+    Hkdf::<Sha256>::new(None, shared.as_bytes())
+        .expand(b"TENDERMINT_SECRET_CONNECTION_KEY_AND_CHALLENGE_GEN", &mut key_material)
+        .map_err(|_| anyhow!("key derivation failed"))?;
+    let recv_secret = if local_is_low { &key_material[0..32] } else { &key_material[32..64] };
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(recv_secret));
+
+    let mut nonce = 0u64;
+    let mut received = vec![];
+    let auth = loop {
+        received.extend(read_frame(stream, &cipher, &mut nonce)?);
+        let mut buf = received.as_slice();
+        if let Ok(auth) = AuthSigMessage::decode_length_delimited(&mut buf) {
+            break auth;
+        }
+        if received.len() > 4 * DATA_MAX_SIZE {
+            return Err(anyhow!("invalid authentication message"));
+        }
+    };
+    let pub_key = auth.pub_key
+        .and_then(|k| PublicKey::from_raw_ed25519(&k.ed25519))
+        .ok_or(anyhow!("remote node has no ed25519 key"))?;
+    let sig = Signature::new(auth.sig)?.ok_or(anyhow!("remote node sent an empty signature"))?;
+    pub_key.verify(&challenge, &sig).map_err(|_| anyhow!("remote node signature is invalid"))?;
+    Ok(account::Id::from(pub_key).to_string().to_lowercase())
+}
+
+/// Resolves a peer host, giving up after `timeout` since the system resolver has no timeout of its own.
+fn resolve(host: &str, port: u16, timeout: Duration) -> Result<SocketAddr> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    let (tx, rx) = mpsc::channel();
+    let name = host.to_string();
+    // a lookup that outlives the timeout finishes in the background and its result is dropped
+    thread::spawn(move || tx.send((name.as_str(), port).to_socket_addrs().map(|mut a| a.next())));
+    match rx.recv_timeout(timeout) {
+        Ok(addr) => addr?.ok_or(anyhow!("no address for {}", host)),
+        Err(_) => Err(anyhow!("resolving {} timed out", host)),
+    }
+}
+
+fn probe_peer(peer: &Peer, options: &ProbeOptions) -> ProbeResult {
+    let result = resolve(&peer.host, peer.port, options.timeout).and_then(|addr| {
+        let start = Instant::now();
+        let mut stream = TcpStream::connect_timeout(&addr, options.timeout)?;
+        let latency = start.elapsed();
+        if options.verify_node_id {
+            stream.set_read_timeout(Some(options.timeout))?;
+            stream.set_write_timeout(Some(options.timeout))?;
+            let node_id = handshake_node_id(&mut stream)?;
+            if node_id != peer.id.to_lowercase() {
+                return Err(anyhow!("node id is {}", node_id));
+            }
+        }
+        Ok(latency)
+    });
+    match result {
+        Ok(latency) => ProbeResult { peer: peer.clone(), latency: Some(latency), error: None },
+        Err(err) => {
+            let timed_out = err.downcast_ref::<io::Error>()
+                .is_some_and(|e| matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock));
+            let error = if timed_out { "timed out".to_string() } else { err.to_string() };
+            ProbeResult { peer: peer.clone(), latency: None, error: Some(error) }
+        },
+    }
+}
+
+/// Dials every peer concurrently and returns the results, reachable peers first and fastest first.
+pub fn probe_peers(peers: &[Peer], options: &ProbeOptions) -> Vec<ProbeResult> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![]);
+    thread::scope(|s| {
+        for _ in 0..options.concurrency.clamp(1, peers.len().max(1)) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let peer = match peers.get(i) {
+                    Some(p) => p,
+                    None => break,
+                };
+                let result = probe_peer(peer, options);
+                results.lock().unwrap().push(result);
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|r| (!r.is_reachable(), r.latency));
+    results
+}
+
+/// Keeps the `count` fastest reachable peers, logging the ones that were dropped.
+pub fn fastest_peers(peers: &[Peer], count: usize, options: &ProbeOptions) -> Vec<Peer> {
+    if peers.is_empty() {
+        return vec![];
+    }
+    let results = probe_peers(peers, options);
+    for r in results.iter().filter(|r| !r.is_reachable()) {
+        warn!("dropping unreachable peer {}: {}", r.peer, r.error.as_deref().unwrap_or_default());
+    }
+    let reachable: Vec<Peer> = results.into_iter()
+        .filter(|r| r.is_reachable())
+        .take(count)
+        .map(|r| r.peer)
+        .collect();
+    info!("{} of {} peers reachable", reachable.len(), peers.len());
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use ed25519_dalek::{Keypair, PublicKey as DalekPublicKey, SecretKey, Signer};
+    use sha2::Digest;
+
+    use super::*;
+
+    /// A handshake with a node using `keypair(7)`, recorded with the ephemeral secrets `[1; 32]` for the prober
+    /// and `[2; 32]` for the node: what the prober sends, then what the node answers.
+    const RECORDED_SENT: &str = "220a20a4e09292b651c278b9772c569f5fa9bb13d906b46ab68c9df9dc2b4409f8a209";
+    const RECORDED_RECEIVED: &str = "\
+        220a20ce8d3ad1ccb633ec7b70c17814a5c76ecd029685050d344745ba05870e587d59fd9898f389b11f6244e491af864146697e5e956fdc\
+        0c6d17db0046530347250ac681437d09d00a4fc6cd15cb46472561d6e7455c22572856fc3940ffabb97aabc6e5516a822a1ca3e459a600e3\
+        1d463845382bcfe3de2f8bcdb87f2ee7bd1afa7016722d70f0312f3fd30f962c96b67b76b7c224091a8d26cfbf21fe635de7ddce49861460\
+        c29ba8941a6d09b2d6b052295bdb079e10269f7dc1e71ecf8bf34e6474e292ddf22fbe33c15210fbeaeefec7e33d167367aa679d00bf5266\
+        cde6b396de930bd5088c81d250f091ee91f357de1a8cf60916365804d25703bec9e80c89d023de23966c2dc4759730fe5ddb454fd159d69f\
+        7c4b500ec7d5f6884d5cebdb8c642797a2028c6fae3a94219d72b565b9fa74adb07bfe53da23940a4c3f6ded9ad147f44ee8efd459cb50e1\
+        7d41f06dea8fc7bc31ce3c1d6bc0700f7a03132889ecc8a61bd08456fa4d0e66c3c27d5d567bb471b84625d5dc1441bd6ec3d5e76b4d4098\
+        f8f6252a017b3c6cf40a8b922396afb778e93eddcff3b382b9cac0631ef5bffd8b988672ba64e4f0b56d1073f5f271b4ec472cac304dad48\
+        7c01159c85140b90967e33c51adfbea3e5f9dc8a898d74373cb9f01fe115ea4d59662a894623570f63514c0c34fbb171f3a1a81d2e6c9641\
+        fee525b784fb190c111281051c1b393eeeeeade443975bd4c1b76cf16230fb4813b91e5e4d88789720ba85c81546581966a707f58772fa9e\
+        ea7505ed6724834b246526058d2cb20f3f1c7998cb082c508c2ce0cff68c300f8574b9f68511ce81403d3662e9c75e243701c980071e3148\
+        06ebf96f34378801b5f404213b19a9f0e7bf70b004a7c3ada48c37d12613f4715e5ed4149f7df0eca9f78f5277f39d3949271622218132a3\
+        8d1f9d600556a6a676f5ff571cea27bc9984821acb99de7c438d08ed8b82555d19b7f35f012a6ba2a2cd39a5c6503a91e2f3a377f1665e4e\
+        3eeb2accfaa32a0bf8ddb23ecbc2b1882bbd3f96c6bb4adc12ef330ee055d9fa6d4b0a854e633803ba14757e562d3b23a5daf7a46b7a8fe4\
+        e45e8125bc34f481f1b8d458be428c28f9f29345fe490a076b09ab2b8652fc33f4ccb9fc08e7c319b1e2387787e871cb7e744acf3e37682e\
+        7d8e72bf00a0c16a00b9d224b3efb6468dba89e340288f12111c4c5074e2c220e44eacf1ad8124b5e626065983530053cfc3906b40c44ca1\
+        af8c78144844600380c0e618deeda925686441c8deb1ae8f08aec00cc7a4e7f571bdd8a41c2bfd1b2b219e04928707d1e35a3d17720bddbc\
+        1dc45324dd351284535690a8efedc50932503a3dc8893c037a9a4a61b2b32e23823c5768ca0842e86afecb21e0f37bbe183063fd15f8ea8d\
+        122901933ef0cde9d29adaf20417f020e99431718892dba4be9ad6b28433b28363056f0f3cea6edeebe5543665a1b70f9ddadc7d01cbea2c\
+        396a0fff3ee2158fb70e5b91b2e380";
+    const RECORDED_NODE_ID: &str = "fe812c12f3ab4ce6ac5db69ac352f906cb1b11ef";
+
+    /// Plays back recorded bytes and keeps what is written.
+    struct Replay {
+        received: io::Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for Replay {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.received.read(buf)
+        }
+    }
+
+    impl Write for Replay {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn replay(received: Vec<u8>) -> (Result<String>, Vec<u8>) {
+        let mut stream = Replay { received: io::Cursor::new(received), sent: vec![] };
+        let node_id = authenticate(&mut stream, StaticSecret::from([1u8; 32]));
+        (node_id, stream.sent)
+    }
+
+    /// Seals one secret connection frame the way the sending side of a Tendermint node does.
+    fn seal_frame(cipher: &ChaCha20Poly1305, nonce: u64, chunk: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; DATA_LEN_SIZE + DATA_MAX_SIZE];
+        frame[..DATA_LEN_SIZE].copy_from_slice(&(chunk.len() as u32).to_le_bytes());
+        frame[DATA_LEN_SIZE..DATA_LEN_SIZE + chunk.len()].copy_from_slice(chunk);
+        let mut nonce_bytes = [0u8; 12];
+        nonce_bytes[4..].copy_from_slice(&nonce.to_le_bytes());
+        let tag = cipher.encrypt_in_place_detached(Nonce::from_slice(&nonce_bytes), b"", &mut frame).unwrap();
+        frame.extend_from_slice(&tag);
+        frame
+    }
+
+    /// Answers one handshake as a node with `key`, signing `signed` instead of the challenge when given and sending
+    /// the authentication message in `chunk` byte frames.
+    fn respond(stream: &mut TcpStream, key: &Keypair, chunk: usize, signed: Option<&[u8]>) {
+        let mut seed = [0u8; 32];
+        getrandom::getrandom(&mut seed).unwrap();
+        let local_secret = StaticSecret::from(seed);
+        let local_public = EphemeralPublic::from(&local_secret);
+        let mut remote_msg = vec![0u8; read_varint(stream).unwrap()];
+        stream.read_exact(&mut remote_msg).unwrap();
+        let remote_bytes: [u8; 32] = BytesValue::decode(remote_msg.as_slice()).unwrap().value.try_into().unwrap();
+        let remote_public = EphemeralPublic::from(remote_bytes);
+        stream.write_all(&BytesValue { value: local_public.as_bytes().to_vec() }.encode_length_delimited_to_vec())
+            .unwrap();
+
+        let shared = local_secret.diffie_hellman(&remote_public);
+        let local_is_low = local_public.as_bytes() < remote_public.as_bytes();
+        let (low, high) = if local_is_low { (&local_public, &remote_public) } else { (&remote_public, &local_public) };
+        let mut transcript = Transcript::new(b"TENDERMINT_SECRET_CONNECTION_TRANSCRIPT_HASH");
+        transcript.append_message(b"EPHEMERAL_LOWER_PUBLIC_KEY", low.as_bytes());
+        transcript.append_message(b"EPHEMERAL_UPPER_PUBLIC_KEY", high.as_bytes());
+        transcript.append_message(b"DH_SECRET", shared.as_bytes());
+        let mut challenge = [0u8; 32];
+        transcript.challenge_bytes(b"SECRET_CONNECTION_MAC", &mut challenge);
+
+        // the lower key receives with the first secret and sends with the second
+        let mut key_material = [0u8; 96];
+        Hkdf::<Sha256>::new(None, shared.as_bytes())
+            .expand(b"TENDERMINT_SECRET_CONNECTION_KEY_AND_CHALLENGE_GEN", &mut key_material)
+            .unwrap();
+        let send_secret = if local_is_low { &key_material[32..64] } else { &key_material[0..32] };
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(send_secret));
+
+        let sig = key.sign(signed.unwrap_or(&challenge));
+        let auth = AuthSigMessage {
+            pub_key: Some(ProtoPublicKey { ed25519: key.public.as_bytes().to_vec() }),
+            sig: sig.to_bytes().to_vec(),
+        }.encode_length_delimited_to_vec();
+        for (nonce, chunk) in auth.chunks(chunk).enumerate() {
+            stream.write_all(&seal_frame(&cipher, nonce as u64, chunk)).unwrap();
+        }
+    }
+
+    fn keypair(i: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[i; 32]).unwrap();
+        let public = DalekPublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    fn node_id(key: &Keypair) -> String {
+        Sha256::digest(key.public.as_bytes())[..20].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Runs the handshake against a responder on a loopback socket.
+    fn loopback(key: Keypair, chunk: usize, signed: Option<Vec<u8>>) -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let responder = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            respond(&mut stream, &key, chunk, signed.as_deref());
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let node_id = handshake_node_id(&mut stream);
+        responder.join().unwrap();
+        node_id
+    }
+
+    #[test]
+    fn handshake_matches_a_recorded_transcript() {
+        let (id, sent) = replay(unhex(RECORDED_RECEIVED));
+        assert_eq!(id.unwrap(), RECORDED_NODE_ID);
+        assert_eq!(node_id(&keypair(7)), RECORDED_NODE_ID);
+        assert_eq!(sent, unhex(RECORDED_SENT));
+    }
+
+    #[test]
+    fn handshake_rejects_a_tampered_transcript() {
+        let mut received = unhex(RECORDED_RECEIVED);
+        let last = received.len() - 1;
+        received[last] ^= 1;
+        assert_eq!(replay(received).0.unwrap_err().to_string(), "secret connection frame failed to decrypt");
+    }
+
+    #[test]
+    fn handshake_authenticates_the_remote_node() {
+        // the ephemeral keys are random, repeat so both sides end up as the lower key
+        for i in 1..=16 {
+            let key = keypair(i);
+            assert_eq!(loopback(keypair(i), DATA_MAX_SIZE, None).unwrap(), node_id(&key));
+        }
+    }
+
+    #[test]
+    fn handshake_reads_authentication_split_across_frames() {
+        // every frame takes the next nonce
+        let key = keypair(7);
+        assert_eq!(loopback(keypair(7), 10, None).unwrap(), node_id(&key));
+    }
+
+    #[test]
+    fn handshake_rejects_a_signature_over_anything_but_the_challenge() {
+        let err = loopback(keypair(7), DATA_MAX_SIZE, Some(vec![0u8; 32])).unwrap_err();
+        assert_eq!(err.to_string(), "remote node signature is invalid");
+    }
+
+    #[test]
+    fn probe_rejects_a_node_with_another_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let responder = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            respond(&mut stream, &keypair(7), DATA_MAX_SIZE, None);
+        });
+        let peer = Peer { id: node_id(&keypair(8)), host: "127.0.0.1".to_string(), port };
+        let options = ProbeOptions { timeout: Duration::from_secs(5), concurrency: 1, verify_node_id: true };
+        let results = probe_peers(&[peer], &options);
+        responder.join().unwrap();
+        assert_eq!(results[0].error.as_deref(), Some(format!("node id is {}", node_id(&keypair(7))).as_str()));
+    }
+
+    #[test]
+    fn resolves_addresses_and_hostnames() {
+        // addresses need no lookup, even without time for one
+        assert_eq!(resolve("127.0.0.1", 26656, Duration::ZERO).unwrap(), "127.0.0.1:26656".parse().unwrap());
+        assert_eq!(resolve("::1", 26656, Duration::ZERO).unwrap(), "[::1]:26656".parse().unwrap());
+        assert!(resolve("localhost", 26656, Duration::from_secs(5)).unwrap().ip().is_loopback());
+    }
+}
@@ -0,0 +1,142 @@
+use std::env;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow};
+use json_patch::merge;
+use log::info;
+use serde_json::{from_str, from_value, to_value, Value};
+
+use crate::config::{default_config, default_wasmd_config, CosmosChainConfig};
+
+/// Directories searched for `<chain-id>.toml` or `<chain-id>.json` profiles, in priority order.
+pub fn profile_dirs(profile_dir: &Option<PathBuf>) -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if let Some(d) = profile_dir {
+        dirs.push(d.clone());
+    }
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")));
+    if let Some(c) = config_home {
+        dirs.push(c.join("starsign").join("chains"));
+    }
+    dirs
+}
+
+fn find_profile(dirs: &[PathBuf], chain_id: &str) -> Option<PathBuf> {
+    dirs.iter()
+        .flat_map(|d| ["toml", "json"].map(|ext| d.join(format!("{}.{}", chain_id, ext))))
+        .find(|p| p.is_file())
+}
+
+fn read_profile(path: &Path) -> Result<Value> {
+    let content = read_to_string(path)?;
+    let profile = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&content).map_err(anyhow::Error::from),
+        _ => from_str(&content).map_err(anyhow::Error::from),
+    };
+    profile.map_err(|err| anyhow!("invalid profile {}: {}", path.to_string_lossy(), err))
+}
+
+fn resolve_profile(dirs: &[PathBuf], chain_id: &str, seen: &mut Vec<String>) -> Result<Option<CosmosChainConfig>> {
+    // a profile inheriting its own chain id extends the built-in chain
+    if seen.last().is_some_and(|s| s == chain_id) {
+        return Ok(default_config(chain_id));
+    }
+    if seen.iter().any(|s| s == chain_id) {
+        return Err(anyhow!("profile inheritance cycle: {} -> {}", seen.join(" -> "), chain_id));
+    }
+    let path = match find_profile(dirs, chain_id) {
+        Some(p) => p,
+        None => return Ok(default_config(chain_id)),
+    };
+    seen.push(chain_id.to_string());
+    let mut patch = read_profile(&path)?;
+    let base = match patch.as_object_mut().and_then(|o| o.remove("inherit")) {
+        Some(Value::String(parent)) => resolve_profile(dirs, &parent, seen)?
+            .ok_or(anyhow!("profile {} inherits unknown chain {}", path.to_string_lossy(), parent))?,
+        Some(_) => return Err(anyhow!("profile {} inherit must be a chain id", path.to_string_lossy())),
+        None => default_wasmd_config(),
+    };
+    let mut cfg = to_value(base)?;
+    merge(&mut cfg, &patch);
+    info!("loaded {} profile from {}", chain_id, path.to_string_lossy());
+    from_value(cfg)
+        .map(Some)
+        .map_err(|err| anyhow!("invalid profile {}: {}", path.to_string_lossy(), err))
+}
+
+/// Resolves a chain from user profiles, falling back to the built-in defaults.
+pub fn chain_profile(dirs: &[PathBuf], chain_id: &str) -> Result<Option<CosmosChainConfig>> {
+    resolve_profile(dirs, chain_id, &mut vec![])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    fn profiles(files: &[(&str, &str)]) -> TempDir {
+        let dir = tempdir().unwrap();
+        for (name, content) in files {
+            write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn layers_profiles_over_their_parents() {
+        let dir = profiles(&[
+            ("devnet-1.toml", "inherit = \"staging-1\"\n[tendermint.consensus]\ntimeout_commit = \"1s\"\n"),
+            ("staging-1.json", r#"{"inherit": "kaiyo-1", "app": {"minimum_gas_prices": "0.01ukuji"}}"#),
+            ("plain-1.json", r#"{"genesis_url": "https://example.com/genesis.json"}"#),
+        ]);
+        let dirs = [dir.path().to_path_buf()];
+        let kaiyo = default_config("kaiyo-1").unwrap();
+        let devnet = chain_profile(&dirs, "devnet-1").unwrap().unwrap();
+        assert_eq!(devnet.tendermint.consensus.timeout_commit, "1s");
+        assert_eq!(devnet.app.minimum_gas_prices, "0.01ukuji");
+        assert_eq!(devnet.genesis_url, kaiyo.genesis_url);
+        assert_eq!(devnet.tendermint.p2p.seeds, kaiyo.tendermint.p2p.seeds);
+        // without inherit a profile starts from the wasmd defaults
+        let plain = chain_profile(&dirs, "plain-1").unwrap().unwrap();
+        assert_eq!(plain.genesis_url, "https://example.com/genesis.json");
+        assert_eq!(plain.app.minimum_gas_prices, default_wasmd_config().app.minimum_gas_prices);
+        assert!(chain_profile(&dirs, "unknown-1").unwrap().is_none());
+    }
+
+    #[test]
+    fn earlier_directories_win() {
+        let first = profiles(&[("devnet-1.json", r#"{"genesis_url": "first"}"#)]);
+        let second = profiles(&[("devnet-1.json", r#"{"genesis_url": "second"}"#)]);
+        let dirs = [first.path().to_path_buf(), second.path().to_path_buf()];
+        assert_eq!(chain_profile(&dirs, "devnet-1").unwrap().unwrap().genesis_url, "first");
+    }
+
+    #[test]
+    fn self_inheritance_extends_the_built_in_chain() {
+        let dir = profiles(&[("kaiyo-1.toml", &format!("inherit = \"kaiyo-1\"\ngenesis_sha256 = \"{}\"\n", "ab".repeat(32)))]);
+        let kaiyo = chain_profile(&[dir.path().to_path_buf()], "kaiyo-1").unwrap().unwrap();
+        assert_eq!(kaiyo.genesis_sha256, "ab".repeat(32));
+        assert_eq!(kaiyo.genesis_url, default_config("kaiyo-1").unwrap().genesis_url);
+    }
+
+    #[test]
+    fn rejects_inheritance_cycles_and_unknown_parents() {
+        let dir = profiles(&[
+            ("a-1.json", r#"{"inherit": "b-1"}"#),
+            ("b-1.json", r#"{"inherit": "a-1"}"#),
+            ("c-1.json", r#"{"inherit": "missing-1"}"#),
+            ("d-1.json", r#"{"inherit": 1}"#),
+        ]);
+        let dirs = [dir.path().to_path_buf()];
+        let err = |chain_id| chain_profile(&dirs, chain_id).err().unwrap().to_string();
+        assert_eq!(err("a-1"), "profile inheritance cycle: a-1 -> b-1 -> a-1");
+        assert!(err("c-1").ends_with("inherits unknown chain missing-1"), "{}", err("c-1"));
+        assert!(err("d-1").ends_with("inherit must be a chain id"), "{}", err("d-1"));
+    }
+}
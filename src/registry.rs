@@ -0,0 +1,311 @@
+use std::fs::{read_dir, read_to_string};
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::from_str;
+use serde_json::value::RawValue;
+
+use crate::config::CosmosChainConfig;
+
+/// Gas price as written in `chain.json`, since printing it back from an f64 can change its digits.
+#[derive(Deserialize)]
+#[serde(transparent)]
+pub struct RegistryGasPrice(Box<RawValue>);
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RegistryFeeToken {
+    pub denom: String,
+    pub fixed_min_gas_price: Option<RegistryGasPrice>,
+    pub low_gas_price: Option<RegistryGasPrice>,
+    pub average_gas_price: Option<RegistryGasPrice>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RegistryFees {
+    pub fee_tokens: Vec<RegistryFeeToken>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RegistryGenesis {
+    pub genesis_url: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RegistryCodebase {
+    pub cosmwasm_enabled: Option<bool>,
+    pub genesis: RegistryGenesis,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RegistryPeer {
+    pub id: String,
+    pub address: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RegistryPeers {
+    pub seeds: Vec<RegistryPeer>,
+    pub persistent_peers: Vec<RegistryPeer>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RegistryApi {
+    pub address: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RegistryApis {
+    pub rpc: Vec<RegistryApi>,
+}
+
+/// Subset of a chain-registry `chain.json` used to derive a chain config.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RegistryChain {
+    pub chain_name: String,
+    pub chain_id: String,
+    pub fees: RegistryFees,
+    pub codebase: RegistryCodebase,
+    pub peers: RegistryPeers,
+    pub apis: RegistryApis,
+}
+
+/// Writes a non-negative JSON number, or a string holding one, as a plain decimal without an exponent.
+fn plain_decimal(number: &str) -> Option<String> {
+    let number = number.trim_matches('"');
+    let (mantissa, exp) = match number.split_once(['e', 'E']) {
+        Some((m, e)) => (m, e.parse::<i64>().ok()?),
+        None => (number, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int.is_empty() || !int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits = format!("{}{}", int, frac);
+    let point = int.len() as i64 + exp;
+    let decimal = if point <= 0 {
+        format!("0.{}{}", "0".repeat(-point as usize), digits)
+    } else if point as usize >= digits.len() {
+        format!("{}{}", digits, "0".repeat(point as usize - digits.len()))
+    } else {
+        format!("{}.{}", &digits[..point as usize], &digits[point as usize..])
+    };
+    let decimal = match decimal.split_once('.') {
+        Some((i, f)) if f.trim_end_matches('0').is_empty() => i.to_string(),
+        Some((i, f)) => format!("{}.{}", i, f.trim_end_matches('0')),
+        None => decimal,
+    };
+    let trimmed = decimal.trim_start_matches('0');
+    Some(if trimmed.is_empty() || trimmed.starts_with('.') { format!("0{}", trimmed) } else { trimmed.to_string() })
+}
+
+impl RegistryGasPrice {
+    fn decimal(&self) -> Option<String> {
+        plain_decimal(self.0.get())
+    }
+}
+
+impl RegistryFeeToken {
+    pub fn min_gas_price(&self) -> String {
+        let price = |p: &Option<RegistryGasPrice>| p.as_ref().and_then(|p| p.decimal());
+        price(&self.fixed_min_gas_price)
+            .filter(|p| p != "0")
+            .or_else(|| price(&self.low_gas_price))
+            .or_else(|| price(&self.average_gas_price))
+            .unwrap_or_else(|| "0".to_string())
+    }
+}
+
+fn join_peers(peers: &[RegistryPeer]) -> String {
+    peers.iter()
+        .filter(|p| !p.id.is_empty() && !p.address.is_empty())
+        .map(|p| format!("{}@{}", p.id, p.address))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+impl RegistryChain {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        from_str(&read_to_string(path)?)
+            .map_err(|err| anyhow!("invalid chain registry file {}: {}", path.to_string_lossy(), err))
+    }
+
+    pub fn apply(&self, cfg: &mut CosmosChainConfig) {
+        if !self.fees.fee_tokens.is_empty() {
+            cfg.app.minimum_gas_prices = self.fees.fee_tokens.iter()
+                .map(|t| format!("{}{}", t.min_gas_price(), t.denom))
+                .collect::<Vec<String>>()
+                .join(",");
+        }
+        if self.codebase.cosmwasm_enabled == Some(false) {
+            cfg.app.wasm = None;
+        }
+        if !self.peers.seeds.is_empty() {
+            cfg.tendermint.p2p.seeds = join_peers(&self.peers.seeds);
+        }
+        if !self.peers.persistent_peers.is_empty() {
+            cfg.tendermint.p2p.persistent_peers = join_peers(&self.peers.persistent_peers);
+        }
+        if let Some(url) = self.codebase.genesis.genesis_url.as_ref().filter(|u| !u.is_empty()) {
+            if cfg.genesis_url != *url {
+                cfg.genesis_sha256 = "".to_string();
+            }
+            cfg.genesis_url = url.clone();
+        }
+        let rpc_servers: Vec<String> = self.apis.rpc.iter()
+            .filter(|a| !a.address.is_empty())
+            .map(|a| a.address.clone())
+            .collect();
+        if !rpc_servers.is_empty() {
+            cfg.tendermint.statesync.rpc_servers = rpc_servers;
+        }
+    }
+}
+
+fn search_dir(dir: &Path, chain_id: &str, depth: u8) -> Result<Option<RegistryChain>> {
+    let chain_file = dir.join("chain.json");
+    if chain_file.is_file() {
+        match RegistryChain::from_file(&chain_file) {
+            Ok(chain) if chain.chain_id == chain_id => {
+                info!("found {} in chain registry at {}", chain_id, chain_file.to_string_lossy());
+                return Ok(Some(chain));
+            },
+            Ok(_) => {},
+            Err(err) => warn!("skipping {}", err),
+        }
+    }
+    if depth == 0 {
+        return Ok(None);
+    }
+    let mut subdirs: Vec<_> = read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_dir() && p.file_name().is_some_and(|n| !n.to_string_lossy().starts_with('.')))
+        .collect();
+    subdirs.sort();
+    for subdir in subdirs {
+        if let Some(chain) = search_dir(&subdir, chain_id, depth - 1)? {
+            return Ok(Some(chain));
+        }
+    }
+    Ok(None)
+}
+
+/// Finds a chain in a chain-registry checkout directory or a single `chain.json` file.
+pub fn find_registry_chain(path: &Path, chain_id: Option<&str>) -> Result<Option<RegistryChain>> {
+    if path.is_file() {
+        let chain = RegistryChain::from_file(path)?;
+        return match chain_id {
+            Some(c) if c != chain.chain_id => Err(anyhow!(
+                "chain registry file {} is for {}, not {}", path.to_string_lossy(), chain.chain_id, c,
+            )),
+            _ => Ok(Some(chain)),
+        };
+    }
+    if !path.is_dir() {
+        return Err(anyhow!("chain registry not found: {}", path.to_string_lossy()));
+    }
+    let chain_id = chain_id.ok_or(anyhow!("chain id required to search chain registry directory"))?;
+    search_dir(path, chain_id, 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+
+    use serde_json::{json, Value};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::config::default_wasmd_config;
+
+    fn chain(value: Value) -> RegistryChain {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn keeps_gas_prices_as_written() {
+        let registry: RegistryChain = from_str(r#"{"fees": {"fee_tokens": [
+            {"denom": "ukuji", "fixed_min_gas_price": 0, "low_gas_price": 0.00125, "average_gas_price": 0.0025},
+            {"denom": "ibc/ABC", "fixed_min_gas_price": 0.123456789012345678},
+            {"denom": "uusk", "average_gas_price": 1e-7},
+            {"denom": "aevmos", "fixed_min_gas_price": 2.5E10},
+            {"denom": "ustring", "low_gas_price": "0.0300"},
+            {"denom": "unone"}
+        ]}}"#).unwrap();
+        let mut cfg = default_wasmd_config();
+        registry.apply(&mut cfg);
+        assert_eq!(
+            cfg.app.minimum_gas_prices,
+            "0.00125ukuji,0.123456789012345678ibc/ABC,0.0000001uusk,25000000000aevmos,0.03ustring,0unone",
+        );
+    }
+
+    #[test]
+    fn writes_plain_decimals() {
+        for (number, decimal) in [("0", "0"), ("0.0", "0"), ("10", "10"), ("1.50", "1.5"), ("007.0", "7"), ("1e2", "100"),
+            ("1.25e-3", "0.00125"), ("125E-1", "12.5")] {
+            assert_eq!(plain_decimal(number).as_deref(), Some(decimal), "{}", number);
+        }
+        for number in ["-1", "null", "true", "", ".5", "1e"] {
+            assert_eq!(plain_decimal(number), None, "{}", number);
+        }
+    }
+
+    #[test]
+    fn maps_peers_genesis_and_rpc_servers() {
+        let registry = chain(json!({
+            "chain_id": "kaiyo-1",
+            "codebase": {"cosmwasm_enabled": false, "genesis": {"genesis_url": "https://example.com/genesis.json"}},
+            "peers": {
+                "seeds": [{"id": "aa", "address": "seed.example.com:26656"}, {"id": "", "address": "skipped:26656"}],
+                "persistent_peers": [{"id": "bb", "address": "1.2.3.4:26656"}, {"id": "cc", "address": "5.6.7.8:26656"}],
+            },
+            "apis": {"rpc": [{"address": "https://rpc.example.com"}, {"address": ""}]},
+        }));
+        let mut cfg = default_wasmd_config();
+        cfg.genesis_sha256 = "ab".repeat(32);
+        let gas_prices = cfg.app.minimum_gas_prices.clone();
+        registry.apply(&mut cfg);
+        assert_eq!(cfg.tendermint.p2p.seeds, "aa@seed.example.com:26656");
+        assert_eq!(cfg.tendermint.p2p.persistent_peers, "bb@1.2.3.4:26656,cc@5.6.7.8:26656");
+        assert_eq!(cfg.tendermint.statesync.rpc_servers, vec!["https://rpc.example.com"]);
+        assert_eq!(cfg.genesis_url, "https://example.com/genesis.json");
+        // the pinned hash belonged to the old genesis url
+        assert_eq!(cfg.genesis_sha256, "");
+        assert!(cfg.app.wasm.is_none());
+        assert_eq!(cfg.app.minimum_gas_prices, gas_prices);
+    }
+
+    #[test]
+    fn finds_chains_in_a_registry_checkout() {
+        let registry = tempdir().unwrap();
+        for (dir, chain_id) in [("kujira", "kaiyo-1"), ("testnets/harpoon", "harpoon-4")] {
+            create_dir_all(registry.path().join(dir)).unwrap();
+            write(registry.path().join(dir).join("chain.json"), json!({"chain_id": chain_id}).to_string()).unwrap();
+        }
+        create_dir_all(registry.path().join("broken")).unwrap();
+        write(registry.path().join("broken/chain.json"), "{").unwrap();
+        for chain_id in ["kaiyo-1", "harpoon-4"] {
+            let found = find_registry_chain(registry.path(), Some(chain_id)).unwrap().unwrap();
+            assert_eq!(found.chain_id, chain_id);
+        }
+        assert!(find_registry_chain(registry.path(), Some("osmosis-1")).unwrap().is_none());
+        assert!(find_registry_chain(registry.path(), None).is_err());
+
+        let file = registry.path().join("kujira/chain.json");
+        assert_eq!(find_registry_chain(&file, None).unwrap().unwrap().chain_id, "kaiyo-1");
+        let err = find_registry_chain(&file, Some("harpoon-4")).err().unwrap().to_string();
+        assert!(err.ends_with("is for kaiyo-1, not harpoon-4"), "{}", err);
+    }
+}
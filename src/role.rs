@@ -0,0 +1,118 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+/// Node role whose preset is layered onto the chain defaults.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Role {
+    /// signs blocks, reachable only through its persistent peers with no public APIs
+    Validator,
+    /// shields a validator, which it keeps private and always connected
+    Sentry,
+    /// crawls the network and hands out peer addresses
+    Seed,
+    /// keeps all state and blocks
+    Archive,
+    /// serves public RPC, API and gRPC
+    Rpc,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Validator => "validator",
+            Role::Sentry => "sentry",
+            Role::Seed => "seed",
+            Role::Archive => "archive",
+            Role::Rpc => "rpc",
+        })
+    }
+}
+
+impl Role {
+    /// Preset as a merge patch over the `{"app", "tendermint"}` chain config sections.
+    pub fn preset(self) -> Value {
+        match self {
+            Role::Validator => json!({
+                "tendermint": {
+                    "p2p": { "pex": false, "seeds": "", "addr_book_strict": false },
+                    "consensus": { "double_sign_check_height": 10 },
+                },
+                "app": {
+                    "api": { "enable": false },
+                    "rosetta": { "enable": false },
+                    "grpc": { "enable": false },
+                    "grpc_web": { "enable": false },
+                },
+            }),
+            Role::Sentry => json!({
+                "tendermint": { "p2p": { "pex": true, "addr_book_strict": false } },
+            }),
+            Role::Seed => json!({
+                "tendermint": {
+                    "p2p": { "pex": true, "seed_mode": true, "max_num_inbound_peers": 1000, "max_num_outbound_peers": 100 },
+                },
+            }),
+            Role::Archive => json!({
+                "app": { "pruning": "nothing", "min_retain_blocks": 0 },
+                "tendermint": { "tx_index": { "indexer": "kv" } },
+            }),
+            Role::Rpc => json!({
+                "tendermint": {
+                    "rpc": {
+                        "laddr": "tcp://0.0.0.0:26657",
+                        "cors_allowed_origins": ["*"],
+                        "max_open_connections": 2000,
+                        "grpc_max_open_connections": 2000,
+                        "max_subscription_clients": 500,
+                    },
+                },
+                "app": {
+                    "api": { "enable": true, "address": "tcp://0.0.0.0:1317", "enabled_unsafe_cors": true, "max_open_connections": 2000 },
+                    "grpc": { "enable": true, "address": "0.0.0.0:9090" },
+                    "grpc_web": { "enable": true, "address": "0.0.0.0:9091", "enable_unsafe_cors": true },
+                },
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+    use json_patch::merge;
+    use serde_json::{from_value, to_value};
+
+    use crate::config::{default_config, default_wasmd_config, CosmosChainConfig, SdkVersion, TendermintVersion};
+    use crate::overrides::Overrides;
+
+    use super::*;
+
+    #[test]
+    fn presets_apply_cleanly_to_every_chain() {
+        let mut chains = vec![default_wasmd_config()];
+        chains.extend(["kaiyo-1", "harpoon-4", "bitcanna-1", "cosmoshub-4", "theta-testnet-001"].map(|c| default_config(c).unwrap()));
+        for role in Role::value_variants() {
+            let preset = role.preset();
+            assert!(preset.as_object().unwrap().keys().all(|k| k == "app" || k == "tendermint"), "{}", role);
+            for cfg in &chains {
+                let mut doc = to_value(cfg).unwrap();
+                merge(&mut doc, &preset);
+                let mut parsed: CosmosChainConfig = from_value(doc.clone()).unwrap();
+                let reparsed = to_value(&parsed).unwrap();
+                Overrides::default().check_keys(&doc, &reparsed, "").unwrap();
+                // every preset value keeps its type, so none is dropped or coerced
+                let mut applied = reparsed.clone();
+                merge(&mut applied, &preset);
+                assert_eq!(applied, reparsed, "{}", role);
+                for (&sdk, &tendermint) in SdkVersion::value_variants().iter().zip(TendermintVersion::value_variants().iter().cycle()) {
+                    parsed.sdk_version = sdk;
+                    parsed.tendermint_version = tendermint;
+                    parsed.get_app_config().unwrap();
+                    parsed.get_tendermint_config().unwrap();
+                }
+            }
+        }
+    }
+}
@@ -1,9 +1,12 @@
 use anyhow::{Result, Error};
-use tendermint_rpc::{Client, HttpClient};
+use tendermint_rpc::{Client, HttpClient, Paging};
 use tokio::runtime::{Builder, Runtime};
 
 pub use tendermint_rpc::endpoint::status::Response as StatusResponse;
 pub use tendermint_rpc::endpoint::block::Response as BlockResponse;
+pub use tendermint_rpc::endpoint::commit::Response as CommitResponse;
+pub use tendermint_rpc::endpoint::net_info::Response as NetInfoResponse;
+pub use tendermint_rpc::endpoint::validators::Response as ValidatorsResponse;
 
 pub struct BlockingRpc {
     client: HttpClient,
@@ -22,6 +25,18 @@ impl BlockingRpc {
         self.runtime.block_on(self.client.block(height)).map_err(Error::from)
     }
 
+    pub fn commit(&self, height: u32) -> Result<CommitResponse> {
+        self.runtime.block_on(self.client.commit(height)).map_err(Error::from)
+    }
+
+    pub fn validators(&self, height: u32) -> Result<ValidatorsResponse> {
+        self.runtime.block_on(self.client.validators(height, Paging::All)).map_err(Error::from)
+    }
+
+    pub fn net_info(&self) -> Result<NetInfoResponse> {
+        self.runtime.block_on(self.client.net_info()).map_err(Error::from)
+    }
+
     pub fn status(&self) -> Result<StatusResponse> {
         self.runtime.block_on(self.client.status()).map_err(Error::from)
     }
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use prost::Message;
+
+use crate::light::{LightClient, TrustRoot};
+use crate::rpc::BlockingRpc;
+
+#[derive(Clone, PartialEq, Message)]
+struct ProtoDuration {
+    #[prost(int64, tag = "1")]
+    seconds: i64,
+    #[prost(int32, tag = "2")]
+    nanos: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct StakingParams {
+    #[prost(message, optional, tag = "1")]
+    unbonding_time: Option<ProtoDuration>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct QueryStakingParamsResponse {
+    #[prost(message, optional, tag = "1")]
+    params: Option<StakingParams>,
+}
+
+#[derive(Debug)]
+pub struct TrustPoint {
+    pub height: u64,
+    pub hash: String,
+    pub servers: Vec<String>,
+}
+
+/// Parses Go-style durations such as `168h`, `1h30m` or `500ms`.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    if s == "0" {
+        return Ok(Duration::ZERO);
+    }
+    if s.is_empty() {
+        return Err(anyhow!("empty duration"));
+    }
+    let mut seconds = 0f64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let num_len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').ok_or(anyhow!("missing unit in duration {}", s))?;
+        let unit_len = rest[num_len..].find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(rest.len() - num_len);
+        let value: f64 = rest[..num_len].parse().map_err(|_| anyhow!("invalid duration {}", s))?;
+        let scale = match &rest[num_len..num_len + unit_len] {
+            "ns" => 1e-9,
+            "us" | "µs" => 1e-6,
+            "ms" => 1e-3,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            unit => return Err(anyhow!("invalid unit {} in duration {}", unit, s)),
+        };
+        seconds += value * scale;
+        rest = &rest[num_len + unit_len..];
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|_| anyhow!("duration {} out of range", s))
+}
+
+fn unique_servers(servers: &[String]) -> Vec<&String> {
+    let mut unique: Vec<&String> = vec![];
+    for s in servers {
+        if !unique.contains(&s) {
+            unique.push(s);
+        }
+    }
+    unique
+}
+
+fn short_error(err: &anyhow::Error) -> String {
+    err.chain()
+        .map(|e| e.to_string().lines().next().unwrap_or_default().trim().to_string())
+        .filter(|e| !e.is_empty())
+        .last()
+        .unwrap_or_default()
+}
+
+fn report(results: &[(&String, Result<String>)]) -> String {
+    results.iter()
+        .map(|(server, result)| match result {
+            Ok(hash) => format!("  {}: {}", server, hash),
+            Err(err) => format!("  {}: error: {}", server, short_error(err)),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Formats a duration for Tendermint config, in the largest of hours, seconds or milliseconds that is exact.
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match d.subsec_nanos() {
+        0 if secs.is_multiple_of(3600) => format!("{}h", secs / 3600),
+        0 => format!("{}s", secs),
+        nanos if nanos.is_multiple_of(1_000_000) => format!("{}ms", d.as_millis()),
+        _ => format!("{}ns", d.as_nanos()),
+    }
+}
+
+/// Queries the `x/staking` unbonding time.
+pub fn unbonding_time(rpc: &BlockingRpc) -> Result<Duration> {
+    let response = rpc.abci_query("/cosmos.staking.v1beta1.Query/Params", vec![])?;
+    let unbonding = QueryStakingParamsResponse::decode(response.as_slice())?
+        .params
+        .and_then(|p| p.unbonding_time)
+        .ok_or(anyhow!("staking params have no unbonding time"))?;
+    Ok(Duration::new(unbonding.seconds.try_into()?, unbonding.nanos.try_into()?))
+}
+
+/// Picks a trust period of 2/3 of the unbonding time, keeping `configured` if one was supplied. Fails when the
+/// unbonding time cannot be queried and there is no configured trust period to fall back on.
+pub fn safe_trust_period(server: &str, configured: Option<&str>) -> Result<Option<String>> {
+    let unbonding = match BlockingRpc::from_url(server).and_then(|rpc| unbonding_time(&rpc)) {
+        Ok(u) => u,
+        Err(err) if configured.is_some() => {
+            warn!("could not query unbonding time from {} to check the trust period: {}", server, short_error(&err));
+            return Ok(None);
+        },
+        Err(err) => return Err(anyhow!(
+            "could not query unbonding time from {} to derive the trust period, use --statesync-trust-period: {}",
+            server, short_error(&err),
+        )),
+    };
+    info!("unbonding time is {}", format_duration(unbonding));
+    if let Some(c) = configured {
+        if parse_duration(c)? >= unbonding {
+            warn!("trust period {} is not below the unbonding time {}, statesync is unsafe", c, format_duration(unbonding));
+        }
+        return Ok(None);
+    }
+    Ok(Some(format_duration(unbonding * 2 / 3)))
+}
+
+/// Picks a snapshot height all servers have reached and requires a quorum of them to agree on its block hash.
+pub fn verified_trust_point(servers: &[String], snapshot_interval: u64, quorum: Option<usize>) -> Result<TrustPoint> {
+    let servers = unique_servers(servers);
+    if servers.is_empty() {
+        return Err(anyhow!("statesync enabled but no rpc servers are configured"));
+    }
+    let quorum = quorum.unwrap_or(servers.len() / 2 + 1);
+    if quorum == 0 {
+        return Err(anyhow!("statesync quorum must be at least 1"));
+    }
+    if quorum > servers.len() {
+        return Err(anyhow!("statesync quorum {} exceeds the {} configured rpc servers", quorum, servers.len()));
+    }
+    if servers.len() == 1 {
+        warn!("only one statesync rpc server configured, trust hash cannot be cross-verified");
+    }
+    let rpcs: Vec<(&String, Result<BlockingRpc>)> = servers.iter()
+        .map(|s| (*s, BlockingRpc::from_url(s)))
+        .collect();
+    let heights: Vec<(&String, Result<u64>)> = rpcs.iter()
+        .map(|(s, rpc)| (*s, match rpc {
+            Ok(r) => r.status().map(|status| status.sync_info.latest_block_height.value()),
+            Err(err) => Err(anyhow!("{}", err)),
+        }))
+        .collect();
+    for (server, height) in &heights {
+        match height {
+            Ok(h) => info!("{} is at height {}", server, h),
+            Err(err) => warn!("{} status failed: {}", server, short_error(err)),
+        }
+    }
+    let latest_height = heights.iter()
+        .filter_map(|(_, h)| h.as_ref().ok())
+        .min()
+        .copied()
+        .ok_or(anyhow!("no statesync rpc server responded"))?;
+    let height = (latest_height / snapshot_interval) * snapshot_interval;
+    let results: Vec<(&String, Result<String>)> = rpcs.iter()
+        .map(|(s, rpc)| (*s, match rpc {
+            Ok(r) => height.try_into()
+                .map_err(anyhow::Error::from)
+                .and_then(|h: u32| r.block(h))
+                .map(|b| b.block_id.hash.to_string()),
+            Err(err) => Err(anyhow!("{}", err)),
+        }))
+        .collect();
+    let mut votes: HashMap<&String, usize> = HashMap::new();
+    for hash in results.iter().filter_map(|(_, r)| r.as_ref().ok()) {
+        *votes.entry(hash).or_default() += 1;
+    }
+    let (hash, count) = votes.iter()
+        .max_by_key(|(_, count)| **count)
+        .map(|(hash, count)| (*hash, *count))
+        .ok_or(anyhow!("no statesync rpc server returned block {}:\n{}", height, report(&results)))?;
+    // a smaller quorum can be met by more than one hash
+    if count < quorum || votes.values().filter(|c| **c >= quorum).count() > 1 {
+        return Err(anyhow!(
+            "statesync rpc servers disagree on block {} ({} of {} agree, {} required):\n{}",
+            height, count, servers.len(), quorum, report(&results),
+        ));
+    }
+    info!("{} of {} statesync rpc servers agree on block {} ({})", count, servers.len(), height, hash);
+    let agreeing = results.iter()
+        .filter(|(_, r)| r.as_ref().ok() == Some(hash))
+        .map(|(s, _)| s.to_string())
+        .collect();
+    Ok(TrustPoint { height, hash: hash.clone(), servers: agreeing })
+}
+
+/// Checks a trust point with light-client verification from `root`.
+pub fn light_verify_trust_point(trust: &TrustPoint, root: &TrustRoot, trust_period: &str) -> Result<()> {
+    let server = trust.servers.first().ok_or(anyhow!("no statesync rpc server to verify against"))?;
+    let rpc = BlockingRpc::from_url(server)?;
+    let hash = LightClient::new(&rpc, parse_duration(trust_period)?).verify(root, trust.height)?;
+    if hash != trust.hash {
+        return Err(anyhow!("light client verified hash {} at height {}, but rpc servers returned {}", hash, trust.height, trust.hash));
+    }
+    info!("light client verified block {} ({}) via {}", trust.height, hash, server);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use serde_json::{json, Value};
+    use tendermint_rpc::endpoint::abci_query::AbciQuery;
+
+    use super::*;
+
+    const UNBONDING_SECONDS: i64 = 21 * 24 * 3600;
+
+    const HASH_A: &str = "6AA59493037B1673949755B88F86B840FB75285485D95FDBA5BE79D28588F2AC";
+    const HASH_B: &str = "030CFF86E5DC2007E3B88C675984F2E66E0E0E9DE5835116DBB65BA58C3C47F9";
+
+    fn status(height: u64) -> Value {
+        json!({
+            "node_info": {
+                "protocol_version": { "p2p": "8", "block": "11", "app": "1" },
+                "id": "0b8e9b2fc583439e365a85f06a8909294f7657e8",
+                "listen_addr": "tcp://0.0.0.0:26656",
+                "network": "mock-1",
+                "version": "0.34.21",
+                "channels": "40202122233038606100",
+                "moniker": "mock",
+                "other": { "tx_index": "on", "rpc_address": "tcp://0.0.0.0:26657" },
+            },
+            "sync_info": {
+                "latest_block_hash": HASH_A,
+                "latest_app_hash": "0600000000000000",
+                "latest_block_height": height.to_string(),
+                "latest_block_time": "2023-01-01T00:00:00Z",
+                "catching_up": false,
+            },
+            "validator_info": {
+                "address": "675F52E8FDA5F4047B8EAF498F946F551ED53DC2",
+                "pub_key": { "type": "tendermint/PubKeyEd25519", "value": "OKAnhjqSneoGRrC37lMmw13vpj3pge3Au8/5Q1YfGq0=" },
+                "voting_power": "10",
+            },
+        })
+    }
+
+    fn block(height: u64, hash: &str) -> Value {
+        let parts = json!({ "total": 1, "hash": "0DCBB02A8DFB86E78859A24426ED1D9D2A2C9C3D5C6CD1851477B98705564DD8" });
+        let empty = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855";
+        json!({
+            "block_id": { "hash": hash, "parts": parts },
+            "block": {
+                "header": {
+                    "version": { "block": "11", "app": "1" },
+                    "chain_id": "mock-1",
+                    "height": height.to_string(),
+                    "time": "2023-01-01T00:00:00Z",
+                    "last_block_id": { "hash": HASH_B, "parts": parts },
+                    "last_commit_hash": empty,
+                    "data_hash": empty,
+                    "validators_hash": empty,
+                    "next_validators_hash": empty,
+                    "consensus_hash": empty,
+                    "app_hash": "0000000000000000",
+                    "last_results_hash": empty,
+                    "evidence_hash": empty,
+                    "proposer_address": "675F52E8FDA5F4047B8EAF498F946F551ED53DC2",
+                },
+                "data": { "txs": [] },
+                "evidence": { "evidence": [] },
+                "last_commit": {
+                    "height": (height - 1).to_string(),
+                    "round": 0,
+                    "block_id": { "hash": HASH_B, "parts": parts },
+                    "signatures": [],
+                },
+            },
+        })
+    }
+
+    /// JSON-RPC node at `height` answering `block` with `hash`, or with an error when there is none.
+    fn serve(height: u64, hash: Option<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                let mut response = match (request["method"].as_str().unwrap(), hash) {
+                    ("status", _) => json!({ "result": status(height) }),
+                    ("block", Some(h)) => {
+                        let height = request["params"]["height"].as_str().unwrap().parse().unwrap();
+                        json!({ "result": block(height, h) })
+                    },
+                    ("block", None) => json!({ "error": { "code": -32603, "message": "Internal error", "data": "height is not available" } }),
+                    ("abci_query", _) => {
+                        let unbonding_time = Some(ProtoDuration { seconds: UNBONDING_SECONDS, nanos: 0 });
+                        let params = QueryStakingParamsResponse { params: Some(StakingParams { unbonding_time }) };
+                        json!({ "result": { "response": AbciQuery { value: params.encode_to_vec(), ..Default::default() } } })
+                    },
+                    (method, _) => panic!("unexpected rpc method {}", method),
+                };
+                response["jsonrpc"] = json!("2.0");
+                response["id"] = request["id"].clone();
+                let response = response.to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(), response,
+                ).unwrap();
+            }
+        });
+        url
+    }
+
+    /// Url of a server that refuses connections.
+    fn dead() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    #[test]
+    fn agreeing_servers_pick_the_latest_common_snapshot_height() {
+        let servers = [serve(4100, Some(HASH_A)), serve(4350, Some(HASH_A)), serve(6000, Some(HASH_A))];
+        let trust = verified_trust_point(&servers, 2000, None).unwrap();
+        assert_eq!((trust.height, trust.hash.as_str(), trust.servers.len()), (4000, HASH_A, 3));
+    }
+
+    #[test]
+    fn a_quorum_outvotes_a_disagreeing_server() {
+        let servers = [serve(4100, Some(HASH_A)), serve(4100, Some(HASH_B)), serve(4100, Some(HASH_A)), dead(), serve(4100, Some(HASH_A))];
+        let trust = verified_trust_point(&servers, 2000, None).unwrap();
+        assert_eq!(trust.hash, HASH_A);
+        assert_eq!(trust.servers, [servers[0].clone(), servers[2].clone(), servers[4].clone()]);
+    }
+
+    #[test]
+    fn disagreement_below_quorum_reports_every_server() {
+        let servers = [serve(4100, Some(HASH_A)), serve(4100, Some(HASH_B)), serve(4100, None)];
+        let err = verified_trust_point(&servers, 2000, Some(2)).unwrap_err().to_string();
+        assert!(err.starts_with("statesync rpc servers disagree on block 4000 (1 of 3 agree, 2 required):"), "{}", err);
+        assert!(err.contains(&format!("  {}: {}", servers[0], HASH_A)), "{}", err);
+        assert!(err.contains(&format!("  {}: {}", servers[1], HASH_B)), "{}", err);
+        assert!(err.contains(&format!("  {}: error: ", servers[2])), "{}", err);
+    }
+
+    #[test]
+    fn a_quorum_met_by_two_hashes_is_a_disagreement() {
+        let servers = [serve(4100, Some(HASH_A)), serve(4100, Some(HASH_B))];
+        let err = verified_trust_point(&servers, 2000, Some(1)).unwrap_err().to_string();
+        assert!(err.starts_with("statesync rpc servers disagree on block 4000"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_quorums_that_cannot_verify() {
+        let servers = [dead(), dead()];
+        let err = |quorum| verified_trust_point(&servers, 2000, Some(quorum)).unwrap_err().to_string();
+        assert_eq!(err(0), "statesync quorum must be at least 1");
+        assert_eq!(err(3), "statesync quorum 3 exceeds the 2 configured rpc servers");
+        assert_eq!(err(1), "no statesync rpc server responded");
+    }
+
+    #[test]
+    fn parses_go_durations() {
+        assert_eq!(parse_duration("168h").unwrap(), Duration::from_secs(168 * 3600));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("0").unwrap(), Duration::ZERO);
+        assert_eq!(parse_duration("0s").unwrap(), Duration::ZERO);
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert_eq!(parse_duration("5d").unwrap_err().to_string(), "invalid unit d in duration 5d");
+        assert_eq!(parse_duration("1h5").unwrap_err().to_string(), "missing unit in duration 1h5");
+        assert_eq!(parse_duration("h").unwrap_err().to_string(), "invalid duration h");
+        assert_eq!(parse_duration("-1h").unwrap_err().to_string(), "invalid duration -1h");
+        assert_eq!(parse_duration("").unwrap_err().to_string(), "empty duration");
+        assert!(parse_duration("99999999999999999999999h").is_err());
+    }
+
+    #[test]
+    fn formats_durations_that_parse_back() {
+        for (d, s) in [
+            (Duration::from_secs(168 * 3600), "168h"),
+            (Duration::from_secs(5400), "5400s"),
+            (Duration::from_millis(500), "500ms"),
+            (Duration::from_nanos(1_500), "1500ns"),
+            (Duration::ZERO, "0h"),
+            (Duration::from_secs(21 * 24 * 3600) * 2 / 3, "336h"),
+            (Duration::from_secs(1) * 2 / 3, "666666666ns"),
+        ] {
+            assert_eq!(format_duration(d), s);
+            assert_eq!(parse_duration(s).unwrap(), d);
+        }
+    }
+
+    #[test]
+    fn derives_the_trust_period_from_the_unbonding_time() {
+        let server = serve(4100, Some(HASH_A));
+        assert_eq!(safe_trust_period(&server, None).unwrap().as_deref(), Some("336h"));
+        // a configured trust period is kept, even when it equals the default
+        assert_eq!(safe_trust_period(&server, Some("168h")).unwrap(), None);
+    }
+
+    #[test]
+    fn requires_a_trust_period_when_the_unbonding_time_is_unknown() {
+        let server = dead();
+        let err = safe_trust_period(&server, None).unwrap_err().to_string();
+        assert!(err.starts_with(&format!("could not query unbonding time from {}", server)), "{}", err);
+        assert!(err.contains("use --statesync-trust-period"), "{}", err);
+        assert_eq!(safe_trust_period(&server, Some("100h")).unwrap(), None);
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::{Result, anyhow};
+use toml_edit::{Document, Item, Table};
+
+/// Struct field names use underscores where some TOML keys use dashes.
+fn same_key(a: &str, b: &str) -> bool {
+    a.replace('-', "_") == b.replace('-', "_")
+}
+
+/// Whether an override path covers the key at `path`, or a value inside it.
+fn overridden(path: &[String], overrides: &[Vec<String>]) -> bool {
+    overrides.iter().any(|o| !o.is_empty() && o.iter().zip(path).all(|(a, b)| same_key(a, b)))
+}
+
+fn repr(item: &Item) -> String {
+    match item {
+        Item::Value(v) => v.clone().decorated("", "").to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn update_table(
+    existing: &mut Table, rendered: &Table, defaults: Option<&Table>, path: &mut Vec<String>, overrides: &[Vec<String>],
+) {
+    for (key, item) in rendered.iter() {
+        path.push(key.to_string());
+        let default = defaults.and_then(|d| d.get(key));
+        match (existing.get_mut(key), item) {
+            (Some(Item::Table(c)), Item::Table(r)) => update_table(c, r, default.and_then(Item::as_table), path, overrides),
+            (None, Item::Table(r)) => {
+                let mut table = Table::new();
+                update_table(&mut table, r, default.and_then(Item::as_table), path, overrides);
+                if !table.is_empty() {
+                    existing.insert(key, Item::Table(table));
+                }
+            },
+            (current, item) if default.is_none_or(|d| repr(d) != repr(item)) || overridden(path, overrides) => {
+                match (current, item) {
+                    (Some(Item::Value(c)), Item::Value(r)) => {
+                        let decor = c.decor().clone();
+                        *c = r.clone();
+                        *c.decor_mut() = decor;
+                    },
+                    (Some(c), r) => *c = r.clone(),
+                    (None, r) => { existing.insert(key, r.clone()); },
+                }
+            },
+            _ => {},
+        }
+        path.pop();
+    }
+}
+
+/// Applies the keys of a rendered config that differ from the rendered chain defaults, or are overridden, onto an
+/// existing one, keeping its other values, comments, formatting and unknown keys.
+pub fn update_toml(existing: &str, rendered: &str, defaults: &str, overrides: &[Vec<String>]) -> Result<String> {
+    let mut doc = existing.parse::<Document>().map_err(|err| anyhow!("invalid existing config: {}", err))?;
+    let rendered = rendered.parse::<Document>().map_err(|err| anyhow!("invalid rendered config: {}", err))?;
+    let defaults = defaults.parse::<Document>().map_err(|err| anyhow!("invalid rendered config: {}", err))?;
+    update_table(doc.as_table_mut(), rendered.as_table(), Some(defaults.as_table()), &mut vec![], overrides);
+    let updated = doc.to_string();
+    // every key is written with a newline, keep the existing file's ending so unchanged files stay unchanged
+    match existing.ends_with('\n') {
+        true => Ok(updated),
+        false => Ok(updated.trim_end_matches('\n').to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULTS: &str = "pruning = \"nothing\"\nhalt-height = 0\n\n[api]\nenable = true\n";
+
+    #[test]
+    fn keeps_values_not_set_by_starsign() {
+        let existing = "# tuned\npruning = \"custom\" # keep\nhalt-height = 0\nextra = 1\n\n[api]\nenable = false\n";
+        let rendered = "pruning = \"nothing\"\nhalt-height = 5\n\n[api]\nenable = true\n";
+        let updated = update_toml(existing, rendered, DEFAULTS, &[]).unwrap();
+        assert_eq!(updated, "# tuned\npruning = \"custom\" # keep\nhalt-height = 5\nextra = 1\n\n[api]\nenable = false\n");
+    }
+
+    #[test]
+    fn applies_overrides_equal_to_defaults() {
+        let existing = "pruning = \"custom\"\nhalt-height = 0\n\n[api]\nenable = false";
+        let overrides = vec![vec!["pruning".to_string()], vec!["api".to_string()]];
+        let updated = update_toml(existing, DEFAULTS, DEFAULTS, &overrides).unwrap();
+        assert_eq!(updated, "pruning = \"nothing\"\nhalt-height = 0\n\n[api]\nenable = true");
+    }
+
+    #[test]
+    fn overrides_only_cover_the_keys_they_set() {
+        let defaults = "[statesync]\nenable = false\ndiscovery_time = \"15s\"\n";
+        let existing = "[statesync]\nenable = false\ndiscovery_time = \"30s\"\n";
+        let rendered = "[statesync]\nenable = true\ndiscovery_time = \"15s\"\n";
+        let overrides = vec![vec!["statesync".to_string(), "enable".to_string()], vec![]];
+        let updated = update_toml(existing, rendered, defaults, &overrides).unwrap();
+        assert_eq!(updated, "[statesync]\nenable = true\ndiscovery_time = \"30s\"\n");
+    }
+}